@@ -0,0 +1,242 @@
+use crate::deps::read_toml;
+use crate::error::Error;
+use crate::index::FileIndex;
+use globset::GlobBuilder;
+
+/// Editions that `cargo_edition()` accepts.
+pub const EDITIONS: &[&str] = &["2015", "2018", "2021", "2024"];
+
+/// What the `Cargo.toml` files under the project root declare. Empty for a
+/// project without a root `Cargo.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CargoProject {
+    /// The root manifest has a `[workspace]` table.
+    pub workspace: bool,
+    /// Edition of the root package, else `[workspace.package]`'s.
+    pub edition: Option<String>,
+    /// The root package, if any, then the workspace members in path order.
+    pub crates: Vec<Crate>,
+}
+
+/// One package of a Cargo project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crate {
+    pub name: String,
+    /// Directory relative to the root with `/` separators; `.` for the root.
+    pub dir: String,
+    /// The declared edition, inherited from the workspace when it says so,
+    /// and `2015` when unset, as in Cargo.
+    pub edition: String,
+    /// Names in `[features]`, sorted.
+    pub features: Vec<String>,
+    /// Has a library target: `[lib]` or `src/lib.rs`.
+    pub lib: bool,
+    /// Has a binary target: `[[bin]]`, `src/main.rs` or `src/bin/*.rs`.
+    pub bin: bool,
+}
+
+impl CargoProject {
+    /// Read the root `Cargo.toml` and those of the workspace members it
+    /// lists.
+    pub fn load(index: &FileIndex) -> Result<Self, Error> {
+        let root = index.root();
+        if !root.join("Cargo.toml").is_file() {
+            return Ok(Self::default());
+        }
+        let doc = read_toml(root, "Cargo.toml")?;
+        let ws = doc.get("workspace");
+        let ws_edition = ws
+            .and_then(|w| w.get("package"))
+            .and_then(|p| p.get("edition"))
+            .and_then(|e| e.as_str());
+        let mut crates = Vec::new();
+        crates.extend(Crate::from_manifest(index, &doc, ".", ws_edition));
+        for manifest in member_manifests(index, &doc)? {
+            let dir = manifest.trim_end_matches("/Cargo.toml");
+            let member = read_toml(root, &manifest)?;
+            crates.extend(Crate::from_manifest(index, &member, dir, ws_edition));
+        }
+        let edition = match crates.first() {
+            Some(c) if c.dir == "." => Some(c.edition.clone()),
+            _ => ws_edition.map(str::to_string),
+        };
+        Ok(Self {
+            workspace: ws.is_some(),
+            edition,
+            crates,
+        })
+    }
+}
+
+impl Crate {
+    /// The package a manifest in `dir` declares, if it has a `[package]`.
+    fn from_manifest(
+        index: &FileIndex,
+        doc: &toml::Table,
+        dir: &str,
+        ws_edition: Option<&str>,
+    ) -> Option<Self> {
+        let package = doc.get("package")?;
+        let edition = match package.get("edition") {
+            Some(toml::Value::String(e)) => Some(e.as_str()),
+            Some(inherit) if inherit.get("workspace").and_then(|w| w.as_bool()) == Some(true) => {
+                ws_edition
+            }
+            _ => None,
+        };
+        let mut features: Vec<String> = doc
+            .get("features")
+            .and_then(|f| f.as_table())
+            .map(|f| f.keys().cloned().collect())
+            .unwrap_or_default();
+        features.sort();
+        let path = |rel: &str| match dir {
+            "." => rel.to_string(),
+            _ => format!("{dir}/{rel}"),
+        };
+        let bin_dir = path("src/bin/");
+        let autobins = package.get("autobins").and_then(|a| a.as_bool()) != Some(false);
+        Some(Self {
+            name: package.get("name")?.as_str()?.to_string(),
+            dir: dir.to_string(),
+            edition: edition.unwrap_or("2015").to_string(),
+            features,
+            lib: doc.contains_key("lib") || index.has(&path("src/lib.rs")),
+            bin: doc.contains_key("bin")
+                || index.has(&path("src/main.rs"))
+                || (autobins
+                    && index
+                        .paths()
+                        .any(|p| p.strip_prefix(&bin_dir).is_some_and(|f| f.ends_with(".rs")))),
+        })
+    }
+}
+
+/// Manifests of the workspace members the root `Cargo.toml` lists, less
+/// excluded ones, in path order. In member globs, `*` does not cross `/`.
+pub fn member_manifests(index: &FileIndex, doc: &toml::Table) -> Result<Vec<String>, Error> {
+    let Some(ws) = doc.get("workspace") else {
+        return Ok(Vec::new());
+    };
+    let list = |key: &str| -> Vec<String> {
+        ws.get(key)
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .map(|s| s.trim_end_matches('/').to_string())
+            .collect()
+    };
+    let exclude = list("exclude");
+    let mut globs = Vec::new();
+    for member in list("members") {
+        let glob = GlobBuilder::new(&format!("{member}/Cargo.toml"))
+            .literal_separator(true)
+            .build()
+            .map_err(|e| {
+                Error::Template(format!(
+                    "invalid workspace member '{member}' in Cargo.toml: {e}"
+                ))
+            })?;
+        globs.push(glob.compile_matcher());
+    }
+    Ok(index
+        .paths()
+        .filter(|p| *p != "Cargo.toml" && globs.iter().any(|g| g.is_match(p)))
+        .filter(|p| {
+            let dir = p.trim_end_matches("/Cargo.toml");
+            !exclude
+                .iter()
+                .any(|x| dir == x || dir.starts_with(&format!("{x}/")))
+        })
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn load(files: &[(&str, &str)]) -> CargoProject {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        for (path, text) in files {
+            let path = td.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        CargoProject::load(&Context::new(td.path()).files()).unwrap()
+    }
+
+    #[test]
+    fn reads_packages_and_workspaces() {
+        let single = load(&[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"tool\"\n[features]\nfast = []\ndefault = [\"fast\"]\n",
+            ),
+            ("src/main.rs", ""),
+        ]);
+        assert!(!single.workspace);
+        assert_eq!(single.edition.as_deref(), Some("2015"));
+        assert_eq!(
+            single.crates,
+            [Crate {
+                name: "tool".into(),
+                dir: ".".into(),
+                edition: "2015".into(),
+                features: vec!["default".into(), "fast".into()],
+                lib: false,
+                bin: true,
+            }]
+        );
+
+        let ws = load(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/old\"]\n\
+                 [workspace.package]\nedition = \"2024\"\n",
+            ),
+            (
+                "crates/core/Cargo.toml",
+                "[package]\nname = \"core\"\nedition.workspace = true\n",
+            ),
+            ("crates/core/src/lib.rs", ""),
+            (
+                "crates/cli/Cargo.toml",
+                "[package]\nname = \"cli\"\nedition = \"2021\"\n[[bin]]\nname = \"x\"\npath = \"x.rs\"\n",
+            ),
+            ("crates/tools/Cargo.toml", "[package]\nname = \"tools\"\n"),
+            ("crates/tools/src/bin/gen.rs", ""),
+            ("crates/old/Cargo.toml", "[package]\nname = \"old\"\n"),
+        ]);
+        assert!(ws.workspace);
+        assert_eq!(ws.edition.as_deref(), Some("2024"));
+        let got: Vec<(&str, &str, &str, bool, bool)> = ws
+            .crates
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.dir.as_str(),
+                    c.edition.as_str(),
+                    c.lib,
+                    c.bin,
+                )
+            })
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("cli", "crates/cli", "2021", false, true),
+                ("core", "crates/core", "2024", true, false),
+                ("tools", "crates/tools", "2015", false, true),
+            ]
+        );
+
+        assert_eq!(load(&[("go.mod", "module x\n")]), CargoProject::default());
+    }
+}
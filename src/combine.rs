@@ -0,0 +1,312 @@
+use crate::markdown::chunks;
+use crate::output::dedupe;
+use crate::template::{Part, SectionMode, flatten};
+use std::collections::{HashMap, HashSet};
+
+/// How the rendered local and shared templates are joined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Merge {
+    /// The local output, then the shared output.
+    #[default]
+    Concat,
+    /// Sections with the same heading path are merged; see [`merge_headings`].
+    Headings,
+}
+
+/// Combine rendered local and shared templates into the final document.
+///
+/// A local section whose name also appears in the shared output is moved into
+/// the shared section's position, where it replaces, appends to, or prepends
+/// to the shared body according to its mode. Local sections with no shared
+/// counterpart are emitted in place. The two outputs are then joined as
+/// `merge` says, the shared one first when `shared_first` is set. With
+/// `dedupe`, paragraphs and sections of the second output that repeat the
+/// first are dropped first.
+pub fn combine(
+    local: &[Part],
+    shared: &[Part],
+    merge: Merge,
+    shared_first: bool,
+    dedupe_repeats: bool,
+) -> String {
+    let (local, shared) = apply_overrides(local, shared);
+    let (mut first, mut second) = if shared_first {
+        (shared, local)
+    } else {
+        (local, shared)
+    };
+    if dedupe_repeats {
+        [first, second] = dedupe(&[&first, &second])
+            .try_into()
+            .expect("one output per input");
+    }
+    match merge {
+        Merge::Concat => first + &second,
+        Merge::Headings => merge_headings(&first, &second),
+    }
+}
+
+/// The local and shared output, with local sections moved into the shared
+/// sections they override.
+fn apply_overrides(local: &[Part], shared: &[Part]) -> (String, String) {
+    let shared_names: HashSet<&str> = shared
+        .iter()
+        .filter_map(|p| match p {
+            Part::Section { name, .. } => Some(name.as_str()),
+            Part::Text(_) => None,
+        })
+        .collect();
+
+    // Local overrides keyed by section name; repeated local sections with the
+    // same name are concatenated, and the first one's mode applies.
+    let mut overrides: HashMap<&str, (SectionMode, String)> = HashMap::new();
+    let mut rest = String::new();
+    for p in local {
+        match p {
+            Part::Section { name, mode, text } if shared_names.contains(name.as_str()) => {
+                overrides
+                    .entry(name.as_str())
+                    .or_insert_with(|| (*mode, String::new()))
+                    .1
+                    .push_str(text);
+            }
+            other => rest.push_str(&flatten(std::slice::from_ref(other))),
+        }
+    }
+
+    let mut out = String::new();
+    for p in shared {
+        match p {
+            Part::Text(s) => out.push_str(s),
+            Part::Section { name, text, .. } => match overrides.get(name.as_str()) {
+                None => out.push_str(text),
+                Some((SectionMode::Replace, local)) => out.push_str(local),
+                Some((SectionMode::Append, local)) => {
+                    out.push_str(text);
+                    out.push_str(local);
+                }
+                Some((SectionMode::Prepend, local)) => {
+                    out.push_str(local);
+                    out.push_str(text);
+                }
+            },
+        }
+    }
+    (rest, out)
+}
+
+/// A Markdown heading and the content under it.
+#[derive(Debug, Default)]
+struct Node<'a> {
+    /// Heading level and line; `None` for the text before the first heading.
+    heading: Option<(usize, &'a str)>,
+    /// Text between the heading and its first subsection.
+    body: String,
+    children: Vec<Node<'a>>,
+}
+
+impl Node<'_> {
+    /// What must match for two sections to merge: level and title.
+    fn key(&self) -> Option<(usize, &str)> {
+        self.heading
+            .map(|(level, line)| (level, line.trim_start().trim_start_matches('#').trim()))
+    }
+}
+
+/// Merge two Markdown documents by heading.
+///
+/// Both are read as trees of ATX‑heading sections. Sections with the same
+/// heading path (level and title at each step) are merged: the local body
+/// comes first, then the shared body, then the merged subsections. Sections
+/// only in `local` keep their place; those only in `shared` follow the local
+/// ones at the same level, in shared order. Text before the first heading is
+/// merged the same way.
+pub fn merge_headings(local: &str, shared: &str) -> String {
+    let mut root = tree(local);
+    merge_node(&mut root, tree(shared));
+    let mut out = String::new();
+    write_node(&mut out, &root);
+    out
+}
+
+fn tree(text: &str) -> Node<'_> {
+    // The root, then the open section at each deeper level.
+    let mut stack: Vec<Node> = vec![Node::default()];
+    let chunks = chunks(text);
+    let mut pos = 0;
+    for c in &chunks {
+        let Some(level) = c.heading else { continue };
+        stack
+            .last_mut()
+            .unwrap()
+            .body
+            .push_str(&text[pos..c.range.start]);
+        while stack.len() > 1
+            && stack
+                .last()
+                .unwrap()
+                .heading
+                .is_some_and(|(l, _)| l >= level)
+        {
+            let done = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(done);
+        }
+        stack.push(Node {
+            heading: Some((level, &text[c.range.clone()])),
+            ..Default::default()
+        });
+        pos = c.range.end;
+    }
+    stack.last_mut().unwrap().body.push_str(&text[pos..]);
+    while stack.len() > 1 {
+        let done = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(done);
+    }
+    stack.pop().unwrap()
+}
+
+fn merge_node<'a>(into: &mut Node<'a>, other: Node<'a>) {
+    into.body = match (into.body.trim().is_empty(), other.body.trim().is_empty()) {
+        (_, true) => std::mem::take(&mut into.body),
+        (true, false) => other.body,
+        (false, false) => format!(
+            "{}\n\n{}",
+            into.body.trim_end(),
+            other.body.trim_start_matches(['\n', '\r'])
+        ),
+    };
+    for child in other.children {
+        match into.children.iter_mut().find(|c| c.key() == child.key()) {
+            Some(existing) => merge_node(existing, child),
+            None => into.children.push(child),
+        }
+    }
+}
+
+fn write_node(out: &mut String, node: &Node) {
+    if let Some((_, line)) = node.heading {
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(line);
+        if !line.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out.push_str(&node.body);
+    for child in &node.children {
+        write_node(out, child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Part {
+        Part::Text(s.to_string())
+    }
+
+    fn section(name: &str, mode: SectionMode, s: &str) -> Part {
+        Part::Section {
+            name: name.to_string(),
+            mode,
+            text: s.to_string(),
+        }
+    }
+
+    #[test]
+    fn combines_sections_by_mode() {
+        struct Case {
+            name: &'static str,
+            local: Vec<Part>,
+            expect: &'static str,
+        }
+        let shared = vec![
+            text("S1\n"),
+            section("testing", SectionMode::Replace, "shared tests\n"),
+            text("S2\n"),
+        ];
+        let cases = vec![
+            Case {
+                name: "no sections concatenates",
+                local: vec![text("L\n")],
+                expect: "L\nS1\nshared tests\nS2\n",
+            },
+            Case {
+                name: "replace",
+                local: vec![
+                    text("L\n"),
+                    section("testing", SectionMode::Replace, "local tests\n"),
+                ],
+                expect: "L\nS1\nlocal tests\nS2\n",
+            },
+            Case {
+                name: "append",
+                local: vec![section("testing", SectionMode::Append, "more\n")],
+                expect: "S1\nshared tests\nmore\nS2\n",
+            },
+            Case {
+                name: "prepend",
+                local: vec![section("testing", SectionMode::Prepend, "first\n")],
+                expect: "S1\nfirst\nshared tests\nS2\n",
+            },
+            Case {
+                name: "unmatched local section stays in place",
+                local: vec![
+                    section("style", SectionMode::Replace, "style\n"),
+                    text("L\n"),
+                ],
+                expect: "style\nL\nS1\nshared tests\nS2\n",
+            },
+        ];
+        for c in cases {
+            assert_eq!(
+                combine(&c.local, &shared, Merge::Concat, false, false),
+                c.expect,
+                "case: {}",
+                c.name
+            );
+        }
+    }
+
+    #[test]
+    fn merges_by_heading() {
+        let cases = [
+            (
+                "same headings merge, local first",
+                "# Guide\nLocal intro.\n\n## Testing\nUse nextest.\n",
+                "# Guide\nShared intro.\n\n## Testing\nRun all tests.\n\n## Style\nfmt\n",
+                "# Guide\nLocal intro.\n\nShared intro.\n\n## Testing\nUse nextest.\n\n\
+                 Run all tests.\n\n## Style\nfmt\n",
+            ),
+            (
+                "local order wins, shared-only sections follow",
+                "## B\nb\n## A\na\n",
+                "## A\nA\n## C\nc\n## B\nB\n",
+                "## B\nb\n\nB\n## A\na\n\nA\n## C\nc\n",
+            ),
+            (
+                "paths must match at every level",
+                "# L\n## Notes\nl\n",
+                "# S\n## Notes\ns\n",
+                "# L\n## Notes\nl\n# S\n## Notes\ns\n",
+            ),
+            (
+                "text before headings and code",
+                "Intro\n```\n# not a heading\n```\n",
+                "Shared\n# Title\nx",
+                "Intro\n```\n# not a heading\n```\n\nShared\n# Title\nx",
+            ),
+            (
+                "missing final newline",
+                "# A\na",
+                "# B\nb\n",
+                "# A\na\n# B\nb\n",
+            ),
+        ];
+        for (name, local, shared, expect) in cases {
+            assert_eq!(merge_headings(local, shared), expect, "case: {name}");
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::index::FileIndex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A file index that is built once, by whichever caller needs it first.
+type IndexCell = Arc<OnceLock<Arc<FileIndex>>>;
+
+/// Evaluation environment shared by all templates rendered for one project.
+#[derive(Debug, Clone)]
+pub struct Context {
+    /// Project root that matchers and facts are evaluated against.
+    pub root: PathBuf,
+    /// Environment variables that `{{ env.NAME }}` is allowed to expose.
+    pub allowed_env: HashSet<String>,
+    /// Which files matchers and facts see.
+    pub walk: WalkOptions,
+    /// Threads used to walk the project; 0 picks one per CPU.
+    pub threads: usize,
+    /// Results of `define`d conditions by definition id, so each is
+    /// evaluated at most once.
+    pub defined: Arc<Mutex<HashMap<usize, bool>>>,
+    /// Keys of the `once` blocks rendered so far.
+    pub once: Arc<Mutex<HashSet<String>>>,
+    /// File indexes by walk options, each built on first use.
+    indexes: Arc<Mutex<HashMap<WalkOptions, IndexCell>>>,
+    /// How many times the project has been walked.
+    walks: Arc<AtomicUsize>,
+}
+
+impl Context {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            allowed_env: HashSet::new(),
+            walk: WalkOptions::default(),
+            threads: 0,
+            defined: Arc::default(),
+            once: Arc::default(),
+            indexes: Arc::default(),
+            walks: Arc::default(),
+        }
+    }
+
+    /// The index of the files under the root for the current walk options.
+    /// It is built by the first call and shared by every clone of this
+    /// context, so one render walks the tree once. The walk happens outside
+    /// the lock on the table of indexes, so only callers that need the same
+    /// index wait for it.
+    pub fn files(&self) -> Arc<FileIndex> {
+        let cell = self
+            .indexes
+            .lock()
+            .unwrap()
+            .entry(self.walk)
+            .or_default()
+            .clone();
+        cell.get_or_init(|| {
+            self.walks.fetch_add(1, Ordering::Relaxed);
+            Arc::new(FileIndex::build(&self.root, &self.walk, self.threads))
+        })
+        .clone()
+    }
+
+    /// How many times the project has been walked through this context and
+    /// its clones.
+    #[cfg(test)]
+    pub fn walks(&self) -> usize {
+        self.walks.load(Ordering::Relaxed)
+    }
+
+    /// Allow the named environment variables to be interpolated.
+    pub fn with_allowed_env<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_env.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Walk the project with `n` threads, or one per CPU when `n` is 0.
+    pub fn with_threads(mut self, n: usize) -> Self {
+        self.threads = n;
+        self
+    }
+}
+
+/// How the project tree is walked when matching files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WalkOptions {
+    /// Include hidden files and directories.
+    pub hidden: bool,
+    /// Skip files excluded by `.gitignore`, `.ignore` and git excludes.
+    pub gitignore: bool,
+    /// Follow symbolic links.
+    pub follow_links: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            hidden: true,
+            gitignore: true,
+            follow_links: false,
+        }
+    }
+}
+
+/// The kind of value a loop variable is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarKind {
+    /// A project file; supports the `dir`, `name` and `stem` attributes.
+    File,
+    /// A plain string such as a language name.
+    Str,
+}
+
+impl VarKind {
+    /// Attributes that may follow a variable of this kind (`f.dir`).
+    pub fn attrs(self) -> &'static [&'static str] {
+        match self {
+            VarKind::File => &["dir", "name", "stem"],
+            VarKind::Str => &[],
+        }
+    }
+}
+
+/// A loop variable's value during one iteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    /// Project file, relative to the root, with `/` separators.
+    File(String),
+    Str(String),
+}
+
+impl Binding {
+    /// The value of this binding, or of one of its attributes.
+    pub fn get(&self, attr: Option<&str>) -> Option<String> {
+        match (self, attr) {
+            (Binding::File(p) | Binding::Str(p), None) => Some(p.clone()),
+            (Binding::File(p), Some("dir")) => Some(match p.rsplit_once('/') {
+                Some((dir, _)) => dir.to_string(),
+                None => ".".to_string(),
+            }),
+            (Binding::File(p), Some("name")) => Some(
+                p.rsplit_once('/')
+                    .map_or(p.as_str(), |(_, n)| n)
+                    .to_string(),
+            ),
+            (Binding::File(p), Some("stem")) => {
+                let name = p.rsplit_once('/').map_or(p.as_str(), |(_, n)| n);
+                Some(match name.rsplit_once('.') {
+                    Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+                    _ => name.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Loop variables visible while rendering, innermost last.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    vars: Vec<(String, Binding)>,
+}
+
+impl Scope {
+    /// A copy of this scope with `name` bound to `value`.
+    pub fn with(&self, name: &str, value: Binding) -> Self {
+        let mut vars = self.vars.clone();
+        vars.push((name.to_string(), value));
+        Self { vars }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.vars
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, b)| b)
+    }
+}
@@ -0,0 +1,413 @@
+use crate::cargo::member_manifests;
+use crate::error::Error;
+use crate::facts::read_manifest;
+use crate::index::FileIndex;
+use std::fmt;
+use std::path::Path;
+
+/// A package ecosystem whose manifests `dep()` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ecosystem {
+    /// `Cargo.toml`, including workspace members.
+    Cargo,
+    /// `package.json`.
+    Npm,
+    /// `pyproject.toml` and `requirements*.txt`.
+    Python,
+    /// `go.mod`.
+    Go,
+}
+
+impl Ecosystem {
+    pub const ALL: [Ecosystem; 4] = [
+        Ecosystem::Cargo,
+        Ecosystem::Npm,
+        Ecosystem::Python,
+        Ecosystem::Go,
+    ];
+
+    /// The ecosystem named `name` in a `dep(ECO:NAME)` scope.
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.to_string() == name)
+    }
+
+    /// `name` as this ecosystem compares package names: Cargo treats `-`
+    /// and `_` alike, and Python names are normalized as in PEP 503.
+    fn normalize(self, name: &str) -> String {
+        match self {
+            Ecosystem::Cargo => name.replace('_', "-"),
+            Ecosystem::Python => {
+                let mut out = String::new();
+                for part in name
+                    .to_ascii_lowercase()
+                    .split(['-', '_', '.'])
+                    .filter(|p| !p.is_empty())
+                {
+                    if !out.is_empty() {
+                        out.push('-');
+                    }
+                    out.push_str(part);
+                }
+                out
+            }
+            Ecosystem::Npm | Ecosystem::Go => name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Ecosystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Ecosystem::Cargo => "cargo",
+            Ecosystem::Npm => "npm",
+            Ecosystem::Python => "python",
+            Ecosystem::Go => "go",
+        })
+    }
+}
+
+/// A package that one of the project's manifests depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub eco: Ecosystem,
+    /// Normalized for the ecosystem.
+    pub name: String,
+    /// Only needed to develop or test the project.
+    pub dev: bool,
+}
+
+impl Dependency {
+    fn new(eco: Ecosystem, name: &str, dev: bool) -> Self {
+        Self {
+            eco,
+            name: eco.normalize(name),
+            dev,
+        }
+    }
+
+    /// Whether this dependency is `name`, which is normalized the same way.
+    pub fn is(&self, name: &str) -> bool {
+        self.name == self.eco.normalize(name)
+    }
+}
+
+/// Every dependency declared by the manifests at the project root and by the
+/// members of a Cargo workspace there.
+pub fn collect(index: &FileIndex) -> Result<Vec<Dependency>, Error> {
+    let mut out = Vec::new();
+    let root = index.root();
+    if root.join("Cargo.toml").is_file() {
+        let doc = read_toml(root, "Cargo.toml")?;
+        cargo(&doc, &mut out);
+        for member in member_manifests(index, &doc)? {
+            cargo(&read_toml(root, &member)?, &mut out);
+        }
+    }
+    if root.join("package.json").is_file() {
+        npm(root, &mut out)?;
+    }
+    if root.join("pyproject.toml").is_file() {
+        pyproject(&read_toml(root, "pyproject.toml")?, &mut out);
+    }
+    for file in index.matching("requirements*.txt")? {
+        let txt = read_manifest(&root.join(&file))?;
+        let dev = dev_group(&file);
+        for line in txt.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.starts_with('-')
+                && let Some(name) = requirement_name(line)
+            {
+                out.push(Dependency::new(Ecosystem::Python, name, dev));
+            }
+        }
+    }
+    if root.join("go.mod").is_file() {
+        go(&read_manifest(&root.join("go.mod"))?, &mut out);
+    }
+    Ok(out)
+}
+
+pub(crate) fn read_toml(root: &Path, rel: &str) -> Result<toml::Table, Error> {
+    let path = root.join(rel);
+    toml::from_str(&read_manifest(&path)?)
+        .map_err(|e| Error::Template(format!("invalid {}: {e}", path.display())))
+}
+
+/// Add the dependencies of a `Cargo.toml`, including target‑specific ones
+/// and `[workspace.dependencies]`.
+fn cargo(doc: &toml::Table, out: &mut Vec<Dependency>) {
+    let targets = doc.get("target").and_then(|t| t.as_table());
+    let scopes = std::iter::once(doc).chain(
+        targets
+            .into_iter()
+            .flat_map(|t| t.values().filter_map(|v| v.as_table())),
+    );
+    for scope in scopes {
+        for (table, dev) in [
+            ("dependencies", false),
+            ("build-dependencies", false),
+            ("dev-dependencies", true),
+        ] {
+            cargo_table(scope.get(table), dev, out);
+        }
+    }
+    let workspace = doc.get("workspace").and_then(|w| w.get("dependencies"));
+    cargo_table(workspace, false, out);
+}
+
+/// Add the entries of a Cargo dependency table, under both the key and a
+/// renamed `package`.
+fn cargo_table(table: Option<&toml::Value>, dev: bool, out: &mut Vec<Dependency>) {
+    for (key, spec) in table.and_then(|t| t.as_table()).into_iter().flatten() {
+        out.push(Dependency::new(Ecosystem::Cargo, key, dev));
+        if let Some(package) = spec.get("package").and_then(|p| p.as_str()) {
+            out.push(Dependency::new(Ecosystem::Cargo, package, dev));
+        }
+    }
+}
+
+fn npm(root: &Path, out: &mut Vec<Dependency>) -> Result<(), Error> {
+    let path = root.join("package.json");
+    let doc: serde_json::Value = serde_json::from_str(&read_manifest(&path)?)
+        .map_err(|e| Error::Template(format!("invalid {}: {e}", path.display())))?;
+    for (table, dev) in [
+        ("dependencies", false),
+        ("peerDependencies", false),
+        ("optionalDependencies", false),
+        ("devDependencies", true),
+    ] {
+        for name in doc
+            .get(table)
+            .and_then(|t| t.as_object())
+            .into_iter()
+            .flatten()
+        {
+            out.push(Dependency::new(Ecosystem::Npm, name.0, dev));
+        }
+    }
+    Ok(())
+}
+
+/// Add the dependencies of a `pyproject.toml`: PEP 621 and PEP 735 lists,
+/// and Poetry tables.
+fn pyproject(doc: &toml::Table, out: &mut Vec<Dependency>) {
+    let mut requirements = |list: Option<&toml::Value>, dev: bool| {
+        let items = list.and_then(|l| l.as_array()).into_iter().flatten();
+        for name in items
+            .filter_map(|v| v.as_str())
+            .filter_map(requirement_name)
+        {
+            out.push(Dependency::new(Ecosystem::Python, name, dev));
+        }
+    };
+    let project = doc.get("project");
+    requirements(project.and_then(|p| p.get("dependencies")), false);
+    let groups = [
+        project.and_then(|p| p.get("optional-dependencies")),
+        doc.get("dependency-groups"),
+    ];
+    for (group, list) in groups
+        .into_iter()
+        .flatten()
+        .filter_map(|g| g.as_table())
+        .flatten()
+    {
+        requirements(Some(list), dev_group(group));
+    }
+
+    let Some(poetry) = doc.get("tool").and_then(|t| t.get("poetry")) else {
+        return;
+    };
+    let mut keys = |table: Option<&toml::Value>, dev: bool| {
+        for name in table
+            .and_then(|t| t.as_table())
+            .into_iter()
+            .flatten()
+            .map(|e| e.0)
+        {
+            if name != "python" {
+                out.push(Dependency::new(Ecosystem::Python, name, dev));
+            }
+        }
+    };
+    keys(poetry.get("dependencies"), false);
+    keys(poetry.get("dev-dependencies"), true);
+    let groups = poetry.get("group").and_then(|g| g.as_table());
+    for (group, spec) in groups.into_iter().flatten() {
+        keys(spec.get("dependencies"), dev_group(group));
+    }
+}
+
+/// Whether a dependency group or requirements file with this name is for
+/// development, such as `dev`, `test` or `requirements-dev.txt`.
+fn dev_group(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.contains("dev") || name.contains("test")
+}
+
+/// The package name at the start of a PEP 508 requirement such as
+/// `requests[socks]>=2.0; python_version < "3.8"`.
+fn requirement_name(req: &str) -> Option<&str> {
+    let req = req.trim();
+    let end = req
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(req.len());
+    (end > 0).then(|| &req[..end])
+}
+
+/// Add the modules a `go.mod` requires, from `require` lines and blocks.
+fn go(text: &str, out: &mut Vec<Dependency>) {
+    let mut in_block = false;
+    for line in text.lines() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        let spec = if in_block {
+            if line == ")" {
+                in_block = false;
+                continue;
+            }
+            line
+        } else if let Some(rest) = line.strip_prefix("require") {
+            let rest = rest.trim_start();
+            if rest == "(" {
+                in_block = true;
+                continue;
+            }
+            rest
+        } else {
+            continue;
+        };
+        if let Some(module) = spec.split_whitespace().next() {
+            out.push(Dependency::new(Ecosystem::Go, module, false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn project(files: &[(&str, &str)]) -> TempDir {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        for (path, text) in files {
+            let path = td.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        td
+    }
+
+    #[test]
+    fn reads_every_manifest() {
+        let td = project(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/old\"]\n\
+                 [workspace.dependencies]\nserde = \"1\"\n",
+            ),
+            (
+                "crates/app/Cargo.toml",
+                "[dependencies]\ntokio = { workspace = true }\nhttp_body = \"1\"\n\
+                 [dev-dependencies]\ninsta = \"1\"\n\
+                 [target.'cfg(windows)'.dependencies]\nwinapi = \"0.3\"\n\
+                 [build-dependencies]\nrenamed = { package = \"cc\", version = \"1\" }\n",
+            ),
+            (
+                "crates/old/Cargo.toml",
+                "[dependencies]\nasync-std = \"1\"\n",
+            ),
+            (
+                "crates/app/fixtures/x/Cargo.toml",
+                "[dependencies]\nsmol = \"1\"\n",
+            ),
+            (
+                "package.json",
+                r#"{"dependencies": {"react": "^18"}, "devDependencies": {"@types/node": "*"}}"#,
+            ),
+            (
+                "pyproject.toml",
+                "[project]\ndependencies = [\"Django>=4\", \"requests[socks]; python_version<'3.9'\"]\n\
+                 [project.optional-dependencies]\ntest = [\"pytest\"]\n\
+                 [tool.poetry.group.docs.dependencies]\nSphinx = \"*\"\n",
+            ),
+            (
+                "requirements-dev.txt",
+                "# tools\n-r requirements.txt\nruff==0.4 # lint\n",
+            ),
+            (
+                "go.mod",
+                "module example.com/x\n\nrequire github.com/gin-gonic/gin v1.9.0\n\
+                 require (\n\tgolang.org/x/sync v0.1.0 // indirect\n)\n",
+            ),
+        ]);
+        let ctx = Context::new(td.path());
+        let deps = collect(&ctx.files()).unwrap();
+        let has = |eco: Ecosystem, name: &str, dev: bool| {
+            deps.iter()
+                .any(|d| d.eco == eco && d.is(name) && d.dev == dev)
+        };
+        let cases = [
+            (
+                "workspace dependency",
+                Ecosystem::Cargo,
+                "serde",
+                false,
+                true,
+            ),
+            ("member dependency", Ecosystem::Cargo, "tokio", false, true),
+            (
+                "underscores and dashes",
+                Ecosystem::Cargo,
+                "http-body",
+                false,
+                true,
+            ),
+            ("cargo dev", Ecosystem::Cargo, "insta", true, true),
+            ("target dependency", Ecosystem::Cargo, "winapi", false, true),
+            ("renamed package", Ecosystem::Cargo, "cc", false, true),
+            (
+                "excluded member",
+                Ecosystem::Cargo,
+                "async-std",
+                false,
+                false,
+            ),
+            ("nested manifest", Ecosystem::Cargo, "smol", false, false),
+            ("npm", Ecosystem::Npm, "react", false, true),
+            ("npm dev", Ecosystem::Npm, "@types/node", true, true),
+            (
+                "python normalized",
+                Ecosystem::Python,
+                "django",
+                false,
+                true,
+            ),
+            ("python extras", Ecosystem::Python, "requests", false, true),
+            ("python test group", Ecosystem::Python, "pytest", true, true),
+            ("poetry group", Ecosystem::Python, "sphinx", false, true),
+            ("requirements file", Ecosystem::Python, "ruff", true, true),
+            ("go", Ecosystem::Go, "github.com/gin-gonic/gin", false, true),
+            ("go block", Ecosystem::Go, "golang.org/x/sync", false, true),
+        ];
+        for (name, eco, dep, dev, want) in cases {
+            assert_eq!(has(eco, dep, dev), want, "case: {name}");
+        }
+    }
+
+    #[test]
+    fn bad_manifests_name_the_file() {
+        let cases = [
+            ("Cargo.toml", "[dependencies\n"),
+            ("package.json", "{"),
+            ("pyproject.toml", "project = \n"),
+        ];
+        for (file, text) in cases {
+            let td = project(&[(file, text)]);
+            let err = collect(&Context::new(td.path()).files()).unwrap_err();
+            assert!(err.to_string().contains(file), "{file}: {err}");
+        }
+    }
+}
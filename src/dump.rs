@@ -0,0 +1,387 @@
+use crate::context::{Context, Scope};
+use crate::error::{Error, Source, Span};
+use crate::expr::{Expr, Matcher};
+use crate::template::{Block, LoopSource, SectionMode, Template, Trim, eval_condition};
+use serde_json::{Value, json};
+
+/// Version of the JSON layout produced by [`template_json`]. It changes only
+/// when existing fields change meaning or are removed.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Serialize `tpl`, including any included and parent templates the loader
+/// attached, as JSON. The layout is documented in the README.
+///
+/// With `ctx`, every `if` arm is annotated with the result of its condition
+/// for that project and every `if` with the arm it takes. Conditions that
+/// interpolate a loop variable depend on the iteration and are left
+/// unevaluated.
+pub fn template_json(tpl: &Template, ctx: Option<&Context>) -> Value {
+    Dumper { ctx, loop_depth: 0 }.template(tpl)
+}
+
+struct Dumper<'a> {
+    ctx: Option<&'a Context>,
+    /// Number of `for` blocks enclosing the blocks being dumped.
+    loop_depth: usize,
+}
+
+impl Dumper<'_> {
+    fn template(&mut self, tpl: &Template) -> Value {
+        let src = &tpl.source;
+        let extends = tpl.extends.as_ref().map(|ext| {
+            json!({
+                "path": ext.path,
+                "trim": trim(ext.trim),
+                "template": ext.parent.as_deref().map(|p| self.template(p)),
+            })
+        });
+        let front_matter = tpl.front_matter.as_ref().map(|f| {
+            json!({
+                "min_version": f.min_version,
+                "description": f.description,
+                "outputs": f.outputs,
+                "priority": f.priority,
+                "walk": {
+                    "hidden": f.walk.hidden,
+                    "gitignore": f.walk.gitignore,
+                    "follow_links": f.walk.follow_links,
+                },
+                "span": span(f.span, src),
+            })
+        });
+        json!({
+            "path": src.path.as_ref().map(|p| p.display().to_string()),
+            "front_matter": front_matter,
+            "extends": extends,
+            "blocks": self.blocks(&tpl.blocks, src),
+        })
+    }
+
+    fn blocks(&mut self, blocks: &[Block], src: &Source) -> Vec<Value> {
+        blocks.iter().map(|b| self.block(b, src)).collect()
+    }
+
+    fn block(&mut self, block: &Block, src: &Source) -> Value {
+        match block {
+            Block::Text(text) => json!({ "type": "text", "text": text }),
+            Block::Raw { text, open, close } => json!({
+                "type": "raw",
+                "text": text,
+                "open": trim(*open),
+                "close": trim(*close),
+            }),
+            Block::Interpolate { fact, .. } => {
+                json!({ "type": "interpolate", "fact": fact.to_string() })
+            }
+            Block::Comment { text, trim: t } => {
+                json!({ "type": "comment", "text": text, "trim": trim(*t) })
+            }
+            Block::Include {
+                path,
+                template,
+                trim: t,
+                ..
+            } => json!({
+                "type": "include",
+                "path": path,
+                "trim": trim(*t),
+                "template": template.as_ref().map(|tpl| self.template(tpl)),
+            }),
+            Block::If {
+                branches,
+                else_body,
+                else_trim,
+                end_trim,
+            } => {
+                // The arm taken, once known; `unknown` once an earlier
+                // arm's result is missing.
+                let mut taken = None;
+                let mut unknown = false;
+                let mut arms = Vec::new();
+                for (i, b) in branches.iter().enumerate() {
+                    let mut arm = json!({
+                        "cond": expr(&b.cond, src),
+                        "trim": trim(b.trim),
+                        "body": self.blocks(&b.body, src),
+                    });
+                    if let Some(ctx) = self.ctx {
+                        let result = self.eval(&b.cond, ctx);
+                        if taken.is_none() && !unknown {
+                            match result {
+                                Ok(Some(true)) => taken = Some(i),
+                                Ok(Some(false)) => {}
+                                _ => unknown = true,
+                            }
+                        }
+                        match result {
+                            Ok(r) => arm["result"] = json!(r),
+                            Err(e) => {
+                                arm["result"] = Value::Null;
+                                arm["error"] = json!(message(&e));
+                            }
+                        }
+                    }
+                    arms.push(arm);
+                }
+                let else_json = else_body.as_ref().map(
+                    |body| json!({ "trim": trim(*else_trim), "body": self.blocks(body, src) }),
+                );
+                let mut out = json!({
+                    "type": "if",
+                    "branches": arms,
+                    "else": else_json,
+                    "end_trim": trim(*end_trim),
+                });
+                if self.ctx.is_some() {
+                    out["taken"] = match taken {
+                        Some(i) => json!(i),
+                        None if !unknown && else_body.is_some() => json!("else"),
+                        None => Value::Null,
+                    };
+                }
+                out
+            }
+            Block::Section {
+                name,
+                mode,
+                body,
+                open,
+                close,
+            } => json!({
+                "type": "section",
+                "name": name,
+                "mode": match mode {
+                    SectionMode::Replace => "replace",
+                    SectionMode::Append => "append",
+                    SectionMode::Prepend => "prepend",
+                },
+                "open": trim(*open),
+                "close": trim(*close),
+                "body": self.blocks(body, src),
+            }),
+            Block::Slot {
+                name,
+                body,
+                open,
+                close,
+                ..
+            } => json!({
+                "type": "block",
+                "name": name,
+                "open": trim(*open),
+                "close": trim(*close),
+                "body": self.blocks(body, src),
+            }),
+            Block::Define { def, trim: t } => {
+                let mut out = json!({
+                    "type": "define",
+                    "name": def.name,
+                    "cond": expr(&def.expr, src),
+                    "trim": trim(*t),
+                });
+                if let Some(ctx) = self.ctx {
+                    match self.eval(&def.expr, ctx) {
+                        Ok(r) => out["result"] = json!(r),
+                        Err(e) => {
+                            out["result"] = Value::Null;
+                            out["error"] = json!(message(&e));
+                        }
+                    }
+                }
+                out
+            }
+            Block::Once {
+                key,
+                body,
+                open,
+                close,
+            } => json!({
+                "type": "once",
+                "key": key,
+                "open": trim(*open),
+                "close": trim(*close),
+                "body": self.blocks(body, src),
+            }),
+            Block::Super(t) => json!({ "type": "super", "trim": trim(*t) }),
+            Block::For {
+                var,
+                source,
+                body,
+                open,
+                close,
+                span: tag,
+            } => {
+                let source = match source {
+                    LoopSource::Files(glob) => json!({ "type": "files", "glob": glob }),
+                    LoopSource::Languages => json!({ "type": "languages" }),
+                };
+                self.loop_depth += 1;
+                let body = self.blocks(body, src);
+                self.loop_depth -= 1;
+                json!({
+                    "type": "for",
+                    "var": var,
+                    "source": source,
+                    "span": span(*tag, src),
+                    "open": trim(*open),
+                    "close": trim(*close),
+                    "body": body,
+                })
+            }
+        }
+    }
+
+    /// The result of `cond`, or `None` when it depends on a loop variable.
+    fn eval(&self, cond: &Expr, ctx: &Context) -> Result<Option<bool>, Error> {
+        if self.loop_depth > 0 && cond.has_interpolation() {
+            return Ok(None);
+        }
+        eval_condition(cond, ctx, &Scope::default()).map(Some)
+    }
+}
+
+fn expr(e: &Expr, src: &Source) -> Value {
+    match e {
+        Expr::Matcher(m, s) => {
+            let mut out = match m {
+                Matcher::Exists(pattern) => json!({ "type": "exists", "pattern": pattern }),
+                Matcher::EnvExists(name) => json!({ "type": "env", "name": name, "value": null }),
+                Matcher::EnvEquals { name, value } => {
+                    json!({ "type": "env", "name": name, "value": value })
+                }
+                Matcher::Lang(name) => json!({ "type": "lang", "name": name }),
+                Matcher::CargoWorkspace => json!({ "type": "cargo_workspace" }),
+                Matcher::CargoEdition(edition) => {
+                    json!({ "type": "cargo_edition", "edition": edition })
+                }
+                Matcher::CargoFeature(name) => json!({ "type": "cargo_feature", "name": name }),
+                Matcher::Dep { eco, name, dev } => json!({
+                    "type": "dep",
+                    "ecosystem": eco.map(|e| e.to_string()),
+                    "name": name,
+                    "dev": dev,
+                }),
+                Matcher::Contains {
+                    glob,
+                    pattern,
+                    regex,
+                } => json!({
+                    "type": "contains",
+                    "glob": glob,
+                    "pattern": pattern,
+                    "regex": regex,
+                }),
+            };
+            out["span"] = span(*s, src);
+            out
+        }
+        Expr::And(a, b) => json!({ "type": "and", "left": expr(a, src), "right": expr(b, src) }),
+        Expr::Or(a, b) => json!({ "type": "or", "left": expr(a, src), "right": expr(b, src) }),
+        Expr::Not(inner) => json!({ "type": "not", "expr": expr(inner, src) }),
+        Expr::Ref(def, s) => json!({ "type": "ref", "name": def.name, "span": span(*s, src) }),
+    }
+}
+
+fn span(s: Span, src: &Source) -> Value {
+    let (line, column) = src.line_col(s.start);
+    json!({ "start": s.start, "end": s.end, "line": line, "column": column })
+}
+
+fn trim(t: Trim) -> Value {
+    json!({ "left": t.left, "right": t.right })
+}
+
+/// The message of `e` without its location, which the surrounding JSON gives.
+fn message(e: &Error) -> String {
+    match e {
+        Error::Located(d) => d.message.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn serializes_blocks_and_expressions() {
+        let tpl = Template::parse(
+            "# T\n<!-- if exists(\"a\") && !env(X=1) -->{{ project.name }}<!-- else --->b<!-- endif -->",
+        )
+        .unwrap();
+        let got = template_json(&tpl, None);
+        let expect = json!({
+            "path": null,
+            "front_matter": null,
+            "extends": null,
+            "blocks": [
+                { "type": "text", "text": "# T\n" },
+                {
+                    "type": "if",
+                    "branches": [{
+                        "cond": {
+                            "type": "and",
+                            "left": {
+                                "type": "exists",
+                                "pattern": "a",
+                                "span": { "start": 12, "end": 23, "line": 2, "column": 9 },
+                            },
+                            "right": {
+                                "type": "not",
+                                "expr": {
+                                    "type": "env",
+                                    "name": "X",
+                                    "value": "1",
+                                    "span": { "start": 28, "end": 36, "line": 2, "column": 25 },
+                                },
+                            },
+                        },
+                        "trim": { "left": false, "right": false },
+                        "body": [{ "type": "interpolate", "fact": "project.name" }],
+                    }],
+                    "else": {
+                        "trim": { "left": false, "right": true },
+                        "body": [{ "type": "text", "text": "b" }],
+                    },
+                    "end_trim": { "left": false, "right": false },
+                },
+            ],
+        });
+        assert_eq!(got, expect);
+    }
+
+    #[test]
+    fn annotates_conditions_with_results() {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::write(td.path().join("b.txt"), "").unwrap();
+        let tpl = Template::parse(
+            "<!-- if exists(a.txt) -->a<!-- elif exists(b.txt) -->b<!-- endif -->\
+             <!-- if exists(a.txt) -->a<!-- else -->c<!-- endif -->\
+             <!-- for f in files(\"*.txt\") --><!-- if exists(\"{{ f }}\") -->x<!-- endif --><!-- endfor -->\
+             <!-- define b = exists(b.txt) --><!-- if b -->b<!-- endif -->",
+        )
+        .unwrap();
+        let got = template_json(&tpl, Some(&Context::new(td.path())));
+        let blocks = &got["blocks"];
+        let results: Vec<&Value> = blocks[0]["branches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| &b["result"])
+            .collect();
+        assert_eq!(results, [&json!(false), &json!(true)]);
+        assert_eq!(blocks[0]["taken"], json!(1));
+        assert_eq!(blocks[1]["taken"], json!("else"));
+        let in_loop = &blocks[2]["body"][0];
+        assert_eq!(in_loop["branches"][0]["result"], Value::Null);
+        assert_eq!(in_loop["taken"], Value::Null);
+        assert_eq!(blocks[3]["type"], json!("define"));
+        assert_eq!(blocks[3]["result"], json!(true));
+        let cond = &blocks[4]["branches"][0]["cond"];
+        assert_eq!((&cond["type"], &cond["name"]), (&json!("ref"), &json!("b")));
+        assert_eq!(blocks[4]["taken"], json!(0));
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
 use thiserror::Error as ThisError;
 
 #[derive(Debug, ThisError)]
@@ -7,4 +10,249 @@ pub enum Error {
 
     #[error("template parse error: {0}")]
     Template(String),
+
+    /// A template error tied to a position in a template file.
+    #[error("{0}")]
+    Located(Box<Diagnostic>),
+
+    /// Several errors found in one run, in report order.
+    #[error("{}", display_all(.0))]
+    Multiple(Vec<Error>),
+}
+
+fn display_all(errors: &[Error]) -> String {
+    let mut out = errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    out.push_str(&format!("\n\n{} template errors", errors.len()));
+    out
+}
+
+impl Error {
+    /// Fold errors collected during a run into one result: `Ok` when there
+    /// are none, the error itself when there is one, and otherwise all of
+    /// them, grouped by file in the order files were first reported and
+    /// sorted by position within each file.
+    pub fn from_all(errors: Vec<Error>) -> Result<(), Error> {
+        let mut flat = Vec::new();
+        for e in errors {
+            match e {
+                Error::Multiple(inner) => flat.extend(inner),
+                e => flat.push(e),
+            }
+        }
+        if flat.len() <= 1 {
+            return flat.pop().map_or(Ok(()), Err);
+        }
+        let mut files: Vec<Option<PathBuf>> = Vec::new();
+        let mut keyed: Vec<((usize, usize), Error)> = Vec::with_capacity(flat.len());
+        for e in flat {
+            let key = match &e {
+                Error::Located(d) => {
+                    let path = d.source.as_ref().and_then(|s| s.path.clone());
+                    let file = match files.iter().position(|f| *f == path) {
+                        Some(i) => i,
+                        None => {
+                            files.push(path);
+                            files.len() - 1
+                        }
+                    };
+                    (file, d.span.start)
+                }
+                // Unlocated errors follow those of the files reported
+                // before them.
+                _ => (files.len(), 0),
+            };
+            keyed.push((key, e));
+        }
+        keyed.sort_by_key(|(key, _)| *key);
+        Err(Error::Multiple(keyed.into_iter().map(|(_, e)| e).collect()))
+    }
+
+    /// A template error at `span`; the source is attached later by whoever
+    /// knows which template the span points into.
+    pub fn at(span: Span, message: impl Into<String>) -> Self {
+        Error::Located(Box::new(Diagnostic {
+            message: message.into(),
+            span,
+            source: None,
+            phase: Phase::Parse,
+        }))
+    }
+
+    /// Give an unlocated template error the position `span`. Errors that
+    /// already have a position are returned unchanged.
+    pub fn or_at(self, span: Span) -> Self {
+        match self {
+            Error::Template(msg) => Error::at(span, msg),
+            other => other,
+        }
+    }
+
+    /// Shift a located error's span by `by` bytes, for errors found while
+    /// parsing a slice that starts `by` bytes into the template.
+    pub fn offset(mut self, by: usize) -> Self {
+        if let Error::Located(d) = &mut self {
+            d.span = Span::new(d.span.start + by, d.span.end + by);
+        }
+        self
+    }
+
+    /// Mark located errors as found while rendering rather than parsing.
+    pub fn rendering(mut self) -> Self {
+        match &mut self {
+            Error::Located(d) => d.phase = Phase::Render,
+            Error::Multiple(errors) => {
+                for e in std::mem::take(errors) {
+                    errors.push(e.rendering());
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Record that a located error's span points into `source`, unless an
+    /// inner template already claimed it.
+    pub fn in_source(mut self, source: &Arc<Source>) -> Self {
+        match &mut self {
+            Error::Located(d) if d.source.is_none() => d.source = Some(source.clone()),
+            Error::Multiple(errors) => {
+                for e in std::mem::take(errors) {
+                    errors.push(e.in_source(source));
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+/// A half‑open byte range into a template's text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// The text of a template and the file it was read from, if any.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Source {
+    pub path: Option<PathBuf>,
+    pub text: String,
+}
+
+impl Source {
+    /// 1‑based line and column (in characters) of byte offset `pos`.
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let before = &self.text[..pos.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
+
+/// Whether a template error was found while parsing or while rendering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Parse,
+    Render,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Parse => "parse",
+            Phase::Render => "render",
+        })
+    }
+}
+
+/// A template error message with the span it refers to.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub source: Option<Arc<Source>>,
+    pub phase: Phase,
+}
+
+impl fmt::Display for Diagnostic {
+    /// Formats as the message, a `path:line:col` location, and the first line
+    /// of the span underlined with carets.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "template {} error: {}", self.phase, self.message)?;
+        let Some(src) = &self.source else {
+            return write!(f, " (at byte {})", self.span.start);
+        };
+        let start = self.span.start.min(src.text.len());
+        let (line, col) = src.line_col(start);
+        let line_start = src.text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src.text[start..]
+            .find('\n')
+            .map_or(src.text.len(), |i| start + i);
+        let excerpt = src.text[line_start..line_end].trim_end_matches('\r');
+        let end = self.span.end.clamp(start, line_start + excerpt.len());
+        let width = src.text[start..end].chars().count().max(1);
+
+        let gutter = " ".repeat(line.to_string().len());
+        write!(f, "\n{gutter}--> ")?;
+        if let Some(p) = &src.path {
+            write!(f, "{}:", p.display())?;
+        }
+        write!(f, "{line}:{col}")?;
+        write!(f, "\n{gutter} |\n{line} | {excerpt}\n{gutter} | ")?;
+        // Keep tabs so the carets line up with the excerpt.
+        for ch in src.text[line_start..start].chars() {
+            f.write_str(if ch == '\t' { "\t" } else { " " })?;
+        }
+        write!(f, "{}", "^".repeat(width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn located_errors_show_position_and_excerpt() {
+        let src = Arc::new(Source {
+            path: Some(PathBuf::from("shared.md")),
+            text: "# Title\n\toops <!-- endif -->\nmore\n".into(),
+        });
+        let err = Error::at(Span::new(14, 28), "stray 'endif'").in_source(&src);
+        assert_eq!(
+            err.to_string(),
+            "template parse error: stray 'endif'\n \
+             --> shared.md:2:7\n  \
+             |\n\
+             2 | \toops <!-- endif -->\n  \
+             | \t     ^^^^^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn inner_source_wins() {
+        let inner = Arc::new(Source {
+            path: Some(PathBuf::from("inner.md")),
+            ..Default::default()
+        });
+        let outer = Arc::new(Source::default());
+        let err = Error::Template("bad".into())
+            .or_at(Span::new(0, 0))
+            .in_source(&inner)
+            .in_source(&outer);
+        match err {
+            Error::Located(d) => assert_eq!(d.source, Some(inner)),
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
 }
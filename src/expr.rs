@@ -1,93 +1,399 @@
-use crate::error::Error;
-use globset::{GlobBuilder, GlobSetBuilder};
-use ignore::WalkBuilder;
+use crate::cargo::EDITIONS;
+use crate::context::Context;
+use crate::deps::Ecosystem;
+use crate::error::{Error, Span};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::bytes::{Regex, RegexBuilder};
 use std::collections::HashSet;
 use std::env;
-use std::path::Path;
+use std::fmt;
+use std::sync::Arc;
 
 /// Primitive conditions available in the template language.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Matcher {
     Exists(String),
     EnvExists(String),
-    EnvEquals { name: String, value: String },
+    EnvEquals {
+        name: String,
+        value: String,
+    },
     Lang(String),
+    /// `contains(GLOB, PATTERN[, regex])`: a file matching `glob` holds
+    /// `pattern`, literally or as a regular expression.
+    Contains {
+        glob: String,
+        pattern: String,
+        regex: bool,
+    },
+    /// `dep([ECO:]NAME[, dev])`: a manifest declares package `name`.
+    Dep {
+        eco: Option<Ecosystem>,
+        name: String,
+        dev: bool,
+    },
+    /// `cargo_workspace()`: the root `Cargo.toml` has a `[workspace]` table.
+    CargoWorkspace,
+    /// `cargo_edition(EDITION)`: a crate of the project uses this edition.
+    CargoEdition(String),
+    /// `cargo_feature(NAME)`: a crate of the project defines this feature.
+    CargoFeature(String),
 }
 
 /// Boolean expression AST built from matchers and logical operators.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr {
-    Matcher(Matcher),
+    /// A matcher and its position in the template.
+    Matcher(Matcher, Span),
     And(Box<Expr>, Box<Expr>),
     Or(Box<Expr>, Box<Expr>),
     Not(Box<Expr>),
+    /// A `define`d name, resolved by the parser, and its position.
+    Ref(Arc<Definition>, Span),
+}
+
+/// A named condition from `<!-- define NAME = EXPR -->`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    /// Unique for the whole run; results are cached under it.
+    pub id: usize,
+    pub name: String,
+    pub expr: Expr,
 }
 
 impl Expr {
-    /// Evaluate this expression against a project at `root`.
-    pub fn is_match(&self, root: &Path) -> Result<bool, Error> {
+    /// Evaluate this expression against the project in `ctx`. Errors point
+    /// at the matcher that raised them.
+    pub fn is_match(&self, ctx: &Context) -> Result<bool, Error> {
         match self {
-            Expr::Matcher(m) => match m {
-                Matcher::Exists(pattern) => exists_match(root, pattern),
+            Expr::Matcher(m, span) => match m {
+                Matcher::Exists(pattern) => ctx.files().exists(pattern),
                 Matcher::EnvExists(name) => {
                     Ok(env::var(name).map(|v| !v.is_empty()).unwrap_or(false))
                 }
                 Matcher::EnvEquals { name, value } => {
                     Ok(env::var(name).map(|v| v == *value).unwrap_or(false))
                 }
-                Matcher::Lang(name) => lang_match(root, name),
-            },
-            Expr::And(a, b) => Ok(a.is_match(root)? && b.is_match(root)?),
-            Expr::Or(a, b) => Ok(a.is_match(root)? || b.is_match(root)?),
-            Expr::Not(e) => Ok(!e.is_match(root)?),
+                Matcher::Lang(name) => ctx.files().lang(name),
+                Matcher::Contains {
+                    glob,
+                    pattern,
+                    regex,
+                } => ctx.files().contains(glob, pattern, *regex),
+                Matcher::Dep { eco, name, dev } => ctx.files().dep(*eco, name, *dev),
+                Matcher::CargoWorkspace => ctx.files().cargo().map(|c| c.workspace),
+                Matcher::CargoEdition(edition) => ctx
+                    .files()
+                    .cargo()
+                    .map(|c| c.crates.iter().any(|k| k.edition == *edition)),
+                Matcher::CargoFeature(name) => ctx
+                    .files()
+                    .cargo()
+                    .map(|c| c.crates.iter().any(|k| k.features.contains(name))),
+            }
+            .map_err(|e| e.or_at(*span)),
+            Expr::And(a, b) => Ok(a.is_match(ctx)? && b.is_match(ctx)?),
+            Expr::Or(a, b) => Ok(a.is_match(ctx)? || b.is_match(ctx)?),
+            Expr::Not(e) => Ok(!e.is_match(ctx)?),
+            Expr::Ref(def, _) => {
+                if let Some(&v) = ctx.defined.lock().unwrap().get(&def.id) {
+                    return Ok(v);
+                }
+                let v = def.expr.is_match(ctx)?;
+                ctx.defined.lock().unwrap().insert(def.id, v);
+                Ok(v)
+            }
+        }
+    }
+
+    /// The span from the first matcher to the last.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Matcher(_, span) | Expr::Ref(_, span) => *span,
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                let (a, b) = (a.span(), b.span());
+                Span::new(a.start.min(b.start), a.end.max(b.end))
+            }
+            Expr::Not(e) => e.span(),
+        }
+    }
+
+    /// Check what can be checked without a project: every glob and
+    /// `contains()` regex compiles and every `lang()` name and
+    /// `cargo_edition()` edition is known,
+    /// including those in branches that short‑circuiting would never
+    /// evaluate. Arguments with interpolations are skipped, as their value is
+    /// only known when the template is rendered.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors = Vec::new();
+        self.validate_into(&mut errors);
+        Error::from_all(errors)
+    }
+
+    fn validate_into(&self, errors: &mut Vec<Error>) {
+        match self {
+            Expr::Matcher(m, span) => {
+                let res = match m {
+                    Matcher::Exists(p) if !p.contains("{{") => compile_glob("exists", p).map(drop),
+                    Matcher::Lang(n) if !n.contains("{{") => language(n).map(drop),
+                    Matcher::CargoEdition(e) if !e.contains("{{") && !EDITIONS.contains(&&**e) => {
+                        Err(Error::Template(format!(
+                            "unknown Rust edition: {e}; expected one of {}",
+                            EDITIONS.join(", ")
+                        )))
+                    }
+                    Matcher::Contains {
+                        glob,
+                        pattern,
+                        regex,
+                    } => {
+                        let glob = if glob.contains("{{") {
+                            Ok(())
+                        } else {
+                            compile_glob("contains", glob).map(drop)
+                        };
+                        let pattern = if pattern.contains("{{") {
+                            Ok(())
+                        } else {
+                            content_regex(pattern, *regex).map(drop)
+                        };
+                        glob.and(pattern)
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(e) = res {
+                    errors.push(e.or_at(*span));
+                }
+            }
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.validate_into(errors);
+                b.validate_into(errors);
+            }
+            Expr::Not(e) => e.validate_into(errors),
+            // Checked where it was defined.
+            Expr::Ref(..) => {}
+        }
+    }
+
+    /// Whether any matcher argument contains a `{{ … }}` interpolation.
+    pub fn has_interpolation(&self) -> bool {
+        let mut found = false;
+        let _ = self.map_args(&mut |s| {
+            found |= s.contains("{{");
+            Ok(s.to_string())
+        });
+        found
+    }
+
+    /// Add the patterns of this expression's `exists` matchers, including
+    /// those of the definitions it uses, to `out`. Patterns with
+    /// interpolations are skipped.
+    pub fn exists_patterns<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Expr::Matcher(Matcher::Exists(p), _) if !p.contains("{{") => out.push(p),
+            Expr::Matcher(..) => {}
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.exists_patterns(out);
+                b.exists_patterns(out);
+            }
+            Expr::Not(e) => e.exists_patterns(out),
+            Expr::Ref(def, _) => def.expr.exists_patterns(out),
         }
     }
+
+    /// Rebuild this expression with every matcher argument passed through `f`.
+    pub fn map_args(
+        &self,
+        f: &mut dyn FnMut(&str) -> Result<String, Error>,
+    ) -> Result<Expr, Error> {
+        Ok(match self {
+            Expr::Matcher(m, span) => {
+                let m = match m {
+                    Matcher::Exists(p) => Matcher::Exists(f(p)?),
+                    Matcher::EnvExists(n) => Matcher::EnvExists(f(n)?),
+                    Matcher::EnvEquals { name, value } => Matcher::EnvEquals {
+                        name: f(name)?,
+                        value: f(value)?,
+                    },
+                    Matcher::Lang(n) => Matcher::Lang(f(n)?),
+                    Matcher::Contains {
+                        glob,
+                        pattern,
+                        regex,
+                    } => Matcher::Contains {
+                        glob: f(glob)?,
+                        pattern: f(pattern)?,
+                        regex: *regex,
+                    },
+                    Matcher::Dep { eco, name, dev } => Matcher::Dep {
+                        eco: *eco,
+                        name: f(name)?,
+                        dev: *dev,
+                    },
+                    Matcher::CargoWorkspace => Matcher::CargoWorkspace,
+                    Matcher::CargoEdition(e) => Matcher::CargoEdition(f(e)?),
+                    Matcher::CargoFeature(n) => Matcher::CargoFeature(f(n)?),
+                };
+                Expr::Matcher(m, *span)
+            }
+            Expr::And(a, b) => Expr::And(Box::new(a.map_args(f)?), Box::new(b.map_args(f)?)),
+            Expr::Or(a, b) => Expr::Or(Box::new(a.map_args(f)?), Box::new(b.map_args(f)?)),
+            Expr::Not(e) => Expr::Not(Box::new(e.map_args(f)?)),
+            Expr::Ref(def, span) => Expr::Ref(
+                Arc::new(Definition {
+                    id: def.id,
+                    name: def.name.clone(),
+                    expr: def.expr.map_args(f)?,
+                }),
+                *span,
+            ),
+        })
+    }
 }
 
-fn exists_match(root: &Path, pattern: &str) -> Result<bool, Error> {
-    let glob = GlobBuilder::new(pattern)
-        .case_insensitive(false)
-        .build()
-        .map_err(|e| Error::Template(format!("invalid exists() pattern: {e}")))?;
-    let mut gsb = GlobSetBuilder::new();
-    gsb.add(glob);
-    let gs = gsb
-        .build()
-        .map_err(|e| Error::Template(format!("glob build failed: {e}")))?;
-
-    let mut wb = WalkBuilder::new(root);
-    wb.hidden(false)
-        .parents(false)
-        .follow_links(false)
-        .git_ignore(true)
-        .git_exclude(true)
-        .git_global(true);
-
-    for dent in wb.build() {
-        let dent = match dent {
-            Ok(d) => d,
-            Err(_) => continue,
-        };
-        let ft = match dent.file_type() {
-            Some(t) => t,
-            None => continue,
-        };
-        if ft.is_file() {
-            let path = dent.path();
-            let rel = path.strip_prefix(root).unwrap_or(path);
-            if gs.is_match(rel) {
-                return Ok(true);
+impl fmt::Display for Matcher {
+    /// Formats as canonical template syntax: globs are always quoted, names
+    /// and values only when they are not a plain word.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Matcher::Exists(p) => write!(f, "exists({})", quote(p)),
+            Matcher::EnvExists(n) => write!(f, "env({})", word_or_quote(n)),
+            Matcher::EnvEquals { name, value } => {
+                write!(f, "env({}={})", word_or_quote(name), word_or_quote(value))
+            }
+            Matcher::Lang(n) => write!(f, "lang({})", word_or_quote(n)),
+            Matcher::Contains {
+                glob,
+                pattern,
+                regex,
+            } => {
+                write!(f, "contains({}, {}", quote(glob), quote(pattern))?;
+                if *regex {
+                    f.write_str(", regex")?;
+                }
+                f.write_str(")")
             }
+            Matcher::Dep { eco, name, dev } => {
+                let arg = match eco {
+                    Some(eco) => format!("{eco}:{name}"),
+                    None => name.clone(),
+                };
+                let plain = !arg.is_empty()
+                    && arg.chars().all(|c| {
+                        c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '+' | '@' | '/' | ':')
+                    });
+                let arg = if plain { arg } else { quote(&arg) };
+                write!(f, "dep({arg}{})", if *dev { ", dev" } else { "" })
+            }
+            Matcher::CargoWorkspace => f.write_str("cargo_workspace()"),
+            Matcher::CargoEdition(e) => write!(f, "cargo_edition({})", word_or_quote(e)),
+            Matcher::CargoFeature(n) => write!(f, "cargo_feature({})", word_or_quote(n)),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Formats as canonical template syntax, with single spaces around
+    /// binary operators and parentheses only where the tree needs them.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Matcher(m, _) => write!(f, "{m}"),
+            Expr::Ref(def, _) => f.write_str(&def.name),
+            Expr::Or(a, b) => {
+                write!(f, "{a} || ")?;
+                write_operand(f, b, matches!(**b, Expr::Or(..)))
+            }
+            Expr::And(a, b) => {
+                write_operand(f, a, matches!(**a, Expr::Or(..)))?;
+                f.write_str(" && ")?;
+                write_operand(f, b, matches!(**b, Expr::Or(..) | Expr::And(..)))
+            }
+            Expr::Not(e) => {
+                f.write_str("!")?;
+                write_operand(f, e, matches!(**e, Expr::Or(..) | Expr::And(..)))
+            }
+        }
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter<'_>, e: &Expr, parens: bool) -> fmt::Result {
+    if parens {
+        write!(f, "({e})")
+    } else {
+        write!(f, "{e}")
+    }
+}
+
+/// `s` as a double‑quoted string argument that parses back unchanged.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
         }
     }
-    Ok(false)
+    out.push('"');
+    out
+}
+
+/// `s` unquoted when it is a plain word such as `rust`, `C++` or `CI`,
+/// otherwise quoted.
+fn word_or_quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '+' | '#'));
+    if plain { s.to_string() } else { quote(s) }
+}
+
+/// Compile `pattern` for the named matcher.
+pub(crate) fn glob(matcher: &str, pattern: &str) -> Result<Glob, Error> {
+    GlobBuilder::new(pattern)
+        .case_insensitive(false)
+        .build()
+        .map_err(|e| Error::Template(format!("invalid {matcher}() pattern: {e}")))
 }
 
-fn lang_match(root: &Path, name: &str) -> Result<bool, Error> {
-    let lang = match languages::from_name(name) {
-        Some(l) => l,
-        None => return Err(Error::Template(format!("unknown language: {name}"))),
+pub(crate) fn compile_glob(matcher: &str, pattern: &str) -> Result<GlobSet, Error> {
+    let mut gsb = GlobSetBuilder::new();
+    gsb.add(glob(matcher, pattern)?);
+    gsb.build()
+        .map_err(|e| Error::Template(format!("glob build failed: {e}")))
+}
+
+/// Compile the `contains()` search for `pattern`: a regular expression when
+/// `regex` is set, otherwise the literal text. `^` and `$` match at line
+/// boundaries.
+pub(crate) fn content_regex(pattern: &str, regex: bool) -> Result<Regex, Error> {
+    let source = if regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
     };
+    RegexBuilder::new(&source)
+        .multi_line(true)
+        .build()
+        .map_err(|e| Error::Template(format!("invalid contains() regex: {e}")))
+}
+
+/// Root‑relative paths (with `/` separators) of all non‑ignored files
+/// matching `pattern`, sorted so iteration order is deterministic.
+pub fn matching_files(ctx: &Context, pattern: &str) -> Result<Vec<String>, Error> {
+    ctx.files().matching(pattern)
+}
+
+fn language(name: &str) -> Result<&'static languages::Language, Error> {
+    languages::from_name(name).ok_or_else(|| Error::Template(format!("unknown language: {name}")))
+}
+
+/// Lowercased file extensions, without the dot, that `lang(name)` looks for.
+pub fn lang_extensions(name: &str) -> Result<HashSet<String>, Error> {
+    let lang = language(name)?;
     let mut exts: HashSet<String> = HashSet::new();
     if let Some(list) = lang.extensions {
         for e in list {
@@ -97,38 +403,7 @@ fn lang_match(root: &Path, name: &str) -> Result<bool, Error> {
             }
         }
     }
-    if exts.is_empty() {
-        return Ok(false);
-    }
-
-    let mut wb = WalkBuilder::new(root);
-    wb.hidden(false)
-        .parents(false)
-        .follow_links(false)
-        .git_ignore(true)
-        .git_exclude(true)
-        .git_global(true);
-
-    for dent in wb.build() {
-        let dent = match dent {
-            Ok(d) => d,
-            Err(_) => continue,
-        };
-        let ft = match dent.file_type() {
-            Some(t) => t,
-            None => continue,
-        };
-        if ft.is_file() {
-            let path = dent.path();
-            if let Some(ext) = path.extension().and_then(|s| s.to_str())
-                && exts.contains(&ext.to_ascii_lowercase())
-            {
-                return Ok(true);
-            }
-        }
-    }
-
-    Ok(false)
+    Ok(exts)
 }
 
 #[cfg(test)]
@@ -154,6 +429,11 @@ mod tests {
         writeln!(f, "{contents}").unwrap();
     }
 
+    /// A matcher expression with no meaningful position.
+    fn m(matcher: Matcher) -> Expr {
+        Expr::Matcher(matcher, Span::default())
+    }
+
     fn setup(files: &[&str]) -> (TempDir, std::path::PathBuf) {
         let td = TempDir::new().unwrap();
         let root_buf = td.path().to_path_buf();
@@ -176,32 +456,32 @@ mod tests {
             Case {
                 name: "simple true",
                 files: &["Cargo.toml"],
-                expr: Expr::Matcher(Matcher::Exists("Cargo.toml".into())),
+                expr: m(Matcher::Exists("Cargo.toml".into())),
                 expect: true,
             },
             Case {
                 name: "simple false",
                 files: &["Cargo.toml"],
-                expr: Expr::Matcher(Matcher::Exists("README.md".into())),
+                expr: m(Matcher::Exists("README.md".into())),
                 expect: false,
             },
             Case {
                 name: "recursive glob",
                 files: &["src/main.rs"],
-                expr: Expr::Matcher(Matcher::Exists("**/*.rs".into())),
+                expr: m(Matcher::Exists("**/*.rs".into())),
                 expect: true,
             },
             Case {
                 name: "brace alternation",
                 files: &["src/lib.rs"],
-                expr: Expr::Matcher(Matcher::Exists("src/**/{main,lib}.rs".into())),
+                expr: m(Matcher::Exists("src/**/{main,lib}.rs".into())),
                 expect: true,
             },
         ];
 
         for c in cases {
             let (_td, root) = setup(c.files);
-            let got = c.expr.is_match(&root).unwrap();
+            let got = c.expr.is_match(&Context::new(&root)).unwrap();
             assert_eq!(got, c.expect, "case: {}", c.name);
         }
     }
@@ -209,36 +489,105 @@ mod tests {
     #[test]
     fn lang_matches_rust() {
         let (_td, root) = setup(&["src/lib.rs"]);
-        let e = Expr::Matcher(Matcher::Lang("rust".into()));
-        assert!(e.is_match(&root).unwrap());
+        let e = m(Matcher::Lang("rust".into()));
+        assert!(e.is_match(&Context::new(&root)).unwrap());
     }
 
     #[test]
     fn lang_unknown_errors() {
         let (_td, root) = setup(&[]);
-        let e = Expr::Matcher(Matcher::Lang("definitely-not-a-language".into()));
-        let err = e.is_match(&root).unwrap_err();
+        let span = Span::new(8, 40);
+        let e = Expr::Matcher(Matcher::Lang("definitely-not-a-language".into()), span);
+        let err = e.is_match(&Context::new(&root)).unwrap_err();
         match err {
-            Error::Template(msg) => assert!(msg.contains("unknown language")),
+            Error::Located(d) => {
+                assert!(d.message.contains("unknown language"));
+                assert_eq!(d.span, span);
+            }
             other => panic!("unexpected error: {other:?}"),
         }
     }
 
+    #[test]
+    fn contains_searches_file_contents() {
+        let (_td, root) = setup(&[]);
+        write(
+            &root.join("Cargo.toml"),
+            "[package]\nname = \"x\"\n\n[workspace]",
+        );
+        write(&root.join("src/lib.rs"), "//! Docs\n#![no_std]");
+        write(&root.join(".gitignore"), "ignored.rs\n");
+        write(&root.join("ignored.rs"), "needle");
+        fs::write(root.join("data.bin"), b"needle\0").unwrap();
+        fs::write(
+            root.join("big.txt"),
+            format!(
+                "{}needle",
+                " ".repeat(crate::index::MAX_CONTAINS_BYTES as usize)
+            ),
+        )
+        .unwrap();
+        let cases = [
+            ("literal", "Cargo.toml", "[workspace]", false, true),
+            (
+                "literal is not a regex",
+                "Cargo.toml",
+                "[w]orkspace",
+                false,
+                false,
+            ),
+            (
+                "regex at line start",
+                "src/**/*.rs",
+                r"^#!\[no_std\]",
+                true,
+                true,
+            ),
+            (
+                "regex anchors are per line",
+                "**/*.rs",
+                r"^//! Docs$",
+                true,
+                true,
+            ),
+            ("no match", "src/**/*.rs", "std::", false, false),
+            ("glob matches nothing", "*.py", "x", false, false),
+            (
+                "ignored files are skipped",
+                "**/*.rs",
+                "needle",
+                false,
+                false,
+            ),
+            ("binary files are skipped", "*.bin", "needle", false, false),
+            ("large files are skipped", "*.txt", "needle", false, false),
+        ];
+        let ctx = Context::new(&root);
+        for (name, glob, pattern, regex, expect) in cases {
+            let e = m(Matcher::Contains {
+                glob: glob.into(),
+                pattern: pattern.into(),
+                regex,
+            });
+            assert_eq!(e.is_match(&ctx).unwrap(), expect, "case: {name}");
+        }
+    }
+
     #[test]
     fn exists_ignores_dotgitignore() {
         let (_td, root) = setup(&[]);
         write(&root.join(".gitignore"), "*.log\n");
         touch(&root.join("app.log"));
-        let e = Expr::Matcher(Matcher::Exists("**/*.log".into()));
-        assert!(!e.is_match(&root).unwrap());
+        let e = m(Matcher::Exists("**/*.log".into()));
+        assert!(!e.is_match(&Context::new(&root)).unwrap());
     }
 
     #[test]
     fn exists_directories_do_not_match() {
         let (_td, root) = setup(&[]);
         fs::create_dir_all(root.join("src")).unwrap();
-        let e = Expr::Matcher(Matcher::Exists("src".into()));
-        assert!(!e.is_match(&root).unwrap());
+        let e = m(Matcher::Exists("src".into()));
+        assert!(!e.is_match(&Context::new(&root)).unwrap());
     }
 
     #[test]
@@ -254,25 +603,25 @@ mod tests {
             Case {
                 name: "unset => exists false",
                 set: None,
-                expr: Expr::Matcher(Matcher::EnvExists(key.into())),
+                expr: m(Matcher::EnvExists(key.into())),
                 expect: false,
             },
             Case {
                 name: "empty => exists false",
                 set: Some(""),
-                expr: Expr::Matcher(Matcher::EnvExists(key.into())),
+                expr: m(Matcher::EnvExists(key.into())),
                 expect: false,
             },
             Case {
                 name: "non-empty => exists true",
                 set: Some("value"),
-                expr: Expr::Matcher(Matcher::EnvExists(key.into())),
+                expr: m(Matcher::EnvExists(key.into())),
                 expect: true,
             },
             Case {
                 name: "equals match",
                 set: Some("value"),
-                expr: Expr::Matcher(Matcher::EnvEquals {
+                expr: m(Matcher::EnvEquals {
                     name: key.into(),
                     value: "value".into(),
                 }),
@@ -281,7 +630,7 @@ mod tests {
             Case {
                 name: "equals mismatch",
                 set: Some("value"),
-                expr: Expr::Matcher(Matcher::EnvEquals {
+                expr: m(Matcher::EnvEquals {
                     name: key.into(),
                     value: "other".into(),
                 }),
@@ -296,7 +645,7 @@ mod tests {
                 Some(v) => guard.set(v),
                 None => guard.unset(),
             }
-            let got = c.expr.is_match(&root).unwrap();
+            let got = c.expr.is_match(&Context::new(&root)).unwrap();
             assert_eq!(got, c.expect, "case: {}", c.name);
         }
     }
@@ -304,32 +653,70 @@ mod tests {
     #[test]
     fn boolean_ops_and_precedence() {
         let (_td, root) = setup(&["a.txt"]);
-        let exists_a = Expr::Matcher(Matcher::Exists("a.txt".into()));
-        let exists_b = Expr::Matcher(Matcher::Exists("b.txt".into()));
+        let exists_a = m(Matcher::Exists("a.txt".into()));
+        let exists_b = m(Matcher::Exists("b.txt".into()));
         let foo_guard = EnvGuard::new("FOO");
         foo_guard.unset();
         let expr = Expr::Or(
             Box::new(Expr::And(
                 Box::new(exists_a.clone()),
-                Box::new(Expr::Matcher(Matcher::EnvExists("FOO".into()))),
+                Box::new(m(Matcher::EnvExists("FOO".into()))),
             )),
             Box::new(Expr::Not(Box::new(exists_b.clone()))),
         );
-        assert!(expr.is_match(&root).unwrap());
+        assert!(expr.is_match(&Context::new(&root)).unwrap());
         touch(&root.join("b.txt"));
-        assert!(!expr.is_match(&root).unwrap());
+        assert!(!expr.is_match(&Context::new(&root)).unwrap());
         foo_guard.set("1");
-        assert!(expr.is_match(&root).unwrap());
+        assert!(expr.is_match(&Context::new(&root)).unwrap());
+    }
+
+    #[test]
+    fn matching_files_are_sorted() {
+        let (_td, root) = setup(&["crates/b/Cargo.toml", "crates/a/Cargo.toml", "Cargo.toml"]);
+        let got = matching_files(&Context::new(&root), "crates/*/Cargo.toml").unwrap();
+        assert_eq!(got, vec!["crates/a/Cargo.toml", "crates/b/Cargo.toml"]);
+    }
+
+    #[test]
+    fn validate_checks_every_matcher() {
+        let expr = Expr::Or(
+            Box::new(m(Matcher::Lang("rust".into()))),
+            Box::new(Expr::And(
+                Box::new(Expr::Matcher(Matcher::Lang("rsut".into()), Span::new(1, 2))),
+                Box::new(Expr::Not(Box::new(Expr::Matcher(
+                    Matcher::Exists("{foo".into()),
+                    Span::new(3, 4),
+                )))),
+            )),
+        );
+        match expr.validate().unwrap_err() {
+            Error::Multiple(errors) => {
+                let spans: Vec<_> = errors
+                    .iter()
+                    .map(|e| match e {
+                        Error::Located(d) => d.span,
+                        other => panic!("unexpected error: {other:?}"),
+                    })
+                    .collect();
+                assert_eq!(spans, vec![Span::new(1, 2), Span::new(3, 4)]);
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+
+        let interpolated = m(Matcher::Lang("{{ lang }}".into()));
+        assert!(interpolated.validate().is_ok());
     }
 
     #[test]
     fn invalid_glob_reports_error() {
         let (_td, root) = setup(&[]);
-        let e = Expr::Matcher(Matcher::Exists("{foo".into()));
-        let err = e.is_match(&root).unwrap_err();
+        let e = m(Matcher::Exists("{foo".into()));
+        let err = e.is_match(&Context::new(&root)).unwrap_err();
         match err {
-            Error::Template(msg) => assert!(
-                msg.contains("invalid exists() pattern") || msg.contains("glob build failed")
+            Error::Located(d) => assert!(
+                d.message.contains("invalid exists() pattern")
+                    || d.message.contains("glob build failed")
             ),
             other => panic!("unexpected error: {other:?}"),
         }
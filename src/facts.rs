@@ -0,0 +1,434 @@
+use crate::context::{Context, Scope, VarKind};
+use crate::error::{Error, Span};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A project fact that can be interpolated into template text with `{{ … }}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fact {
+    /// `project.name`: file name of the project root directory.
+    ProjectName,
+    /// `package.name`: package name from `Cargo.toml` or `package.json`.
+    PackageName,
+    /// `languages`: detected programming languages, sorted and comma separated.
+    Languages,
+    /// `cargo.members`: packages of the Cargo project, comma separated.
+    CargoMembers,
+    /// `cargo.bins`: packages with a binary target, comma separated.
+    CargoBins,
+    /// `cargo.libs`: packages with a library target, comma separated.
+    CargoLibs,
+    /// `cargo.edition`: edition of the root package or the workspace.
+    CargoEdition,
+    /// `cargo.features`: features defined by any package, sorted and comma
+    /// separated.
+    CargoFeatures,
+    /// `git.branch`: the currently checked out branch.
+    GitBranch,
+    /// `git.default_branch`: the branch `origin/HEAD` points at, falling back
+    /// to a local `main` or `master`.
+    GitDefaultBranch,
+    /// `env.NAME`: an environment variable; only allow‑listed names render.
+    Env(String),
+    /// A variable bound by an enclosing `for`, optionally with an attribute
+    /// (`f`, `f.dir`).
+    Var { name: String, attr: Option<String> },
+}
+
+impl Fact {
+    /// Resolve a fact name as written inside `{{ … }}`.
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        let fact = match name {
+            "project.name" => Fact::ProjectName,
+            "package.name" => Fact::PackageName,
+            "languages" => Fact::Languages,
+            "cargo.members" => Fact::CargoMembers,
+            "cargo.bins" => Fact::CargoBins,
+            "cargo.libs" => Fact::CargoLibs,
+            "cargo.edition" => Fact::CargoEdition,
+            "cargo.features" => Fact::CargoFeatures,
+            "git.branch" => Fact::GitBranch,
+            "git.default_branch" => Fact::GitDefaultBranch,
+            _ => match name.strip_prefix("env.") {
+                Some(var) if !var.is_empty() => Fact::Env(var.to_string()),
+                _ => return Err(Error::Template(format!("unknown fact: {name}"))),
+            },
+        };
+        Ok(fact)
+    }
+
+    /// Resolve a name as written inside `{{ … }}` where loop variables in
+    /// `vars` (innermost last) are in scope. Variables shadow facts.
+    pub fn parse_in(name: &str, vars: &[(&str, VarKind)]) -> Result<Self, Error> {
+        let (head, attr) = match name.split_once('.') {
+            Some((h, a)) => (h, Some(a)),
+            None => (name, None),
+        };
+        let Some((_, kind)) = vars.iter().rev().find(|(v, _)| *v == head) else {
+            return Self::parse(name);
+        };
+        if let Some(a) = attr
+            && !kind.attrs().contains(&a)
+        {
+            return Err(Error::Template(format!(
+                "unknown attribute '{a}' on loop variable '{head}'"
+            )));
+        }
+        Ok(Fact::Var {
+            name: head.to_string(),
+            attr: attr.map(str::to_string),
+        })
+    }
+
+    /// Compute the value of this fact for the project in `ctx`, with loop
+    /// variables taken from `scope`.
+    pub fn resolve(&self, ctx: &Context, scope: &Scope) -> Result<String, Error> {
+        let value = match self {
+            Fact::ProjectName => ctx
+                .root
+                .canonicalize()
+                .unwrap_or_else(|_| ctx.root.clone())
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned()),
+            Fact::PackageName => package_name(&ctx.root)?,
+            Fact::Languages => Some(detected_languages(ctx).join(", ")),
+            Fact::CargoMembers | Fact::CargoBins | Fact::CargoLibs => {
+                let cargo = ctx.files().cargo()?;
+                let names: Vec<&str> = cargo
+                    .crates
+                    .iter()
+                    .filter(|c| match self {
+                        Fact::CargoBins => c.bin,
+                        Fact::CargoLibs => c.lib,
+                        _ => true,
+                    })
+                    .map(|c| c.name.as_str())
+                    .collect();
+                (!cargo.crates.is_empty()).then(|| names.join(", "))
+            }
+            Fact::CargoEdition => ctx.files().cargo()?.edition.clone(),
+            Fact::CargoFeatures => {
+                let cargo = ctx.files().cargo()?;
+                let features: BTreeSet<&str> = cargo
+                    .crates
+                    .iter()
+                    .flat_map(|c| &c.features)
+                    .map(String::as_str)
+                    .collect();
+                (!cargo.crates.is_empty()).then(|| Vec::from_iter(features).join(", "))
+            }
+            Fact::GitBranch => git_branch(&ctx.root),
+            Fact::GitDefaultBranch => git_default_branch(&ctx.root),
+            Fact::Env(name) => {
+                if !ctx.allowed_env.contains(name) {
+                    return Err(Error::Template(format!(
+                        "environment variable {name} is not allow-listed (use --allow-env {name})"
+                    )));
+                }
+                Some(std::env::var(name).unwrap_or_default())
+            }
+            Fact::Var { name, attr } => scope.lookup(name).and_then(|b| b.get(attr.as_deref())),
+        };
+        value.ok_or_else(|| Error::Template(format!("fact {self} is not available")))
+    }
+}
+
+impl fmt::Display for Fact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fact::ProjectName => write!(f, "project.name"),
+            Fact::PackageName => write!(f, "package.name"),
+            Fact::Languages => write!(f, "languages"),
+            Fact::CargoMembers => write!(f, "cargo.members"),
+            Fact::CargoBins => write!(f, "cargo.bins"),
+            Fact::CargoLibs => write!(f, "cargo.libs"),
+            Fact::CargoEdition => write!(f, "cargo.edition"),
+            Fact::CargoFeatures => write!(f, "cargo.features"),
+            Fact::GitBranch => write!(f, "git.branch"),
+            Fact::GitDefaultBranch => write!(f, "git.default_branch"),
+            Fact::Env(name) => write!(f, "env.{name}"),
+            Fact::Var { name, attr: None } => write!(f, "{name}"),
+            Fact::Var {
+                name,
+                attr: Some(attr),
+            } => write!(f, "{name}.{attr}"),
+        }
+    }
+}
+
+/// A run of literal text or a `{{ … }}` reference within a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece<'a> {
+    Lit(String),
+    Ref(&'a str),
+}
+
+/// Split `s` into literal text and `{{ name }}` references. A backslash
+/// before `{{` yields a literal `{{`.
+pub fn split_interpolations(s: &str) -> Result<Vec<Piece<'_>>, Error> {
+    let mut pieces = Vec::new();
+    let mut lit = String::new();
+    let mut rest = s;
+    while let Some(pos) = rest.find("{{") {
+        if rest[..pos].ends_with('\\') {
+            lit.push_str(&rest[..pos - 1]);
+            lit.push_str("{{");
+            rest = &rest[pos + 2..];
+            continue;
+        }
+        lit.push_str(&rest[..pos]);
+        let after = &rest[pos + 2..];
+        let open = s.len() - rest.len() + pos;
+        let end = after.find("}}").ok_or_else(|| {
+            Error::at(
+                Span::new(open, open + 2),
+                "unterminated interpolation; missing '}}'",
+            )
+        })?;
+        let name = after[..end].trim();
+        if name.is_empty() {
+            return Err(Error::at(
+                Span::new(open, open + end + 4),
+                "empty interpolation",
+            ));
+        }
+        if !lit.is_empty() {
+            pieces.push(Piece::Lit(std::mem::take(&mut lit)));
+        }
+        pieces.push(Piece::Ref(name));
+        rest = &after[end + 2..];
+    }
+    lit.push_str(rest);
+    if !lit.is_empty() {
+        pieces.push(Piece::Lit(lit));
+    }
+    Ok(pieces)
+}
+
+/// Substitute every `{{ … }}` reference in `s`, as used in matcher arguments.
+pub fn interpolate(s: &str, ctx: &Context, scope: &Scope) -> Result<String, Error> {
+    let mut out = String::new();
+    for piece in split_interpolations(s)? {
+        match piece {
+            Piece::Lit(l) => out.push_str(&l),
+            Piece::Ref(name) => {
+                let head = name.split_once('.').map_or(name, |(h, _)| h);
+                let fact = match scope.lookup(head) {
+                    Some(_) => Fact::Var {
+                        name: head.to_string(),
+                        attr: name.split_once('.').map(|(_, a)| a.to_string()),
+                    },
+                    None => Fact::parse(name)?,
+                };
+                out.push_str(&fact.resolve(ctx, scope)?);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn package_name(root: &Path) -> Result<Option<String>, Error> {
+    let cargo = root.join("Cargo.toml");
+    if cargo.is_file() {
+        let txt = read_manifest(&cargo)?;
+        let doc: toml::Table = toml::from_str(&txt)
+            .map_err(|e| Error::Template(format!("invalid {}: {e}", cargo.display())))?;
+        if let Some(name) = doc
+            .get("package")
+            .and_then(|p| p.get("name"))
+            .and_then(|n| n.as_str())
+        {
+            return Ok(Some(name.to_string()));
+        }
+    }
+    let npm = root.join("package.json");
+    if npm.is_file() {
+        let txt = read_manifest(&npm)?;
+        let doc: serde_json::Value = serde_json::from_str(&txt)
+            .map_err(|e| Error::Template(format!("invalid {}: {e}", npm.display())))?;
+        if let Some(name) = doc.get("name").and_then(|n| n.as_str()) {
+            return Ok(Some(name.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+pub(crate) fn read_manifest(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path)
+        .map_err(|e| Error::Template(format!("cannot read {}: {e}", path.display())))
+}
+
+/// Names of programming languages with at least one non‑ignored source file.
+pub fn detected_languages(ctx: &Context) -> Vec<String> {
+    ctx.files().languages()
+}
+
+fn git_branch(root: &Path) -> Option<String> {
+    let head = fs::read_to_string(root.join(".git/HEAD")).ok()?;
+    head.trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(str::to_string)
+}
+
+fn git_default_branch(root: &Path) -> Option<String> {
+    let git = root.join(".git");
+    if let Ok(head) = fs::read_to_string(git.join("refs/remotes/origin/HEAD"))
+        && let Some(branch) = head.trim().strip_prefix("ref: refs/remotes/origin/")
+    {
+        return Some(branch.to_string());
+    }
+    let packed = fs::read_to_string(git.join("packed-refs")).unwrap_or_default();
+    for candidate in ["main", "master"] {
+        let refname = format!("refs/heads/{candidate}");
+        if git.join(&refname).is_file() || packed.lines().any(|l| l.ends_with(&refname)) {
+            return Some(candidate.to_string());
+        }
+    }
+    git_branch(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::EnvGuard;
+    use tempfile::TempDir;
+
+    fn setup(files: &[(&str, &str)]) -> TempDir {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        for (name, contents) in files {
+            let path = td.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        td
+    }
+
+    #[test]
+    fn parse_rejects_unknown_facts() {
+        assert_eq!(Fact::parse("package.name").unwrap(), Fact::PackageName);
+        assert_eq!(Fact::parse("env.CI").unwrap(), Fact::Env("CI".to_string()));
+        for bad in ["package", "env.", "nope"] {
+            let err = Fact::parse(bad).unwrap_err();
+            assert!(err.to_string().contains("unknown fact"), "{bad}");
+        }
+    }
+
+    #[test]
+    fn loop_variables_shadow_facts() {
+        let vars = [("languages", VarKind::Str), ("f", VarKind::File)];
+        assert_eq!(
+            Fact::parse_in("f.dir", &vars).unwrap(),
+            Fact::Var {
+                name: "f".into(),
+                attr: Some("dir".into())
+            }
+        );
+        assert!(matches!(
+            Fact::parse_in("languages", &vars).unwrap(),
+            Fact::Var { .. }
+        ));
+        assert_eq!(
+            Fact::parse_in("package.name", &vars).unwrap(),
+            Fact::PackageName
+        );
+        let err = Fact::parse_in("languages.dir", &vars).unwrap_err();
+        assert!(err.to_string().contains("unknown attribute"));
+    }
+
+    #[test]
+    fn interpolate_substitutes_bindings() {
+        let td = setup(&[]);
+        let ctx = Context::new(td.path());
+        let scope = Scope::default().with(
+            "f",
+            crate::context::Binding::File("crates/a/Cargo.toml".into()),
+        );
+        let got = interpolate("{{ f.dir }}/src/{{f.stem}}.rs \\{{ x", &ctx, &scope).unwrap();
+        assert_eq!(got, "crates/a/src/Cargo.rs {{ x");
+    }
+
+    #[test]
+    fn package_name_from_manifests() {
+        let td = setup(&[("Cargo.toml", "[package]\nname = \"crate-x\"\n")]);
+        let ctx = Context::new(td.path());
+        assert_eq!(
+            Fact::PackageName.resolve(&ctx, &Scope::default()).unwrap(),
+            "crate-x"
+        );
+
+        let td = setup(&[("package.json", "{\"name\": \"npm-x\"}")]);
+        let ctx = Context::new(td.path());
+        assert_eq!(
+            Fact::PackageName.resolve(&ctx, &Scope::default()).unwrap(),
+            "npm-x"
+        );
+
+        let td = setup(&[]);
+        let ctx = Context::new(td.path());
+        assert!(Fact::PackageName.resolve(&ctx, &Scope::default()).is_err());
+    }
+
+    #[test]
+    fn languages_are_sorted_and_deduplicated() {
+        let td = setup(&[
+            ("src/main.rs", ""),
+            ("src/lib.rs", ""),
+            ("tools/gen.py", ""),
+            ("README.md", ""),
+        ]);
+        let ctx = Context::new(td.path());
+        assert_eq!(
+            Fact::Languages.resolve(&ctx, &Scope::default()).unwrap(),
+            "Python, Rust"
+        );
+    }
+
+    #[test]
+    fn git_branches() {
+        let td = setup(&[
+            (".git/HEAD", "ref: refs/heads/feature\n"),
+            (".git/refs/heads/master", "abc\n"),
+        ]);
+        let ctx = Context::new(td.path());
+        assert_eq!(
+            Fact::GitBranch.resolve(&ctx, &Scope::default()).unwrap(),
+            "feature"
+        );
+        assert_eq!(
+            Fact::GitDefaultBranch
+                .resolve(&ctx, &Scope::default())
+                .unwrap(),
+            "master"
+        );
+        fs::create_dir_all(td.path().join(".git/refs/remotes/origin")).unwrap();
+        fs::write(
+            td.path().join(".git/refs/remotes/origin/HEAD"),
+            "ref: refs/remotes/origin/trunk\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Fact::GitDefaultBranch
+                .resolve(&ctx, &Scope::default())
+                .unwrap(),
+            "trunk"
+        );
+    }
+
+    #[test]
+    fn env_requires_allow_list() {
+        let key = "AGENTS_TEST_FACT_ENV";
+        let guard = EnvGuard::new(key);
+        guard.set("value");
+        let td = setup(&[]);
+        let fact = Fact::Env(key.to_string());
+        let err = fact
+            .resolve(&Context::new(td.path()), &Scope::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("not allow-listed"));
+        let ctx = Context::new(td.path()).with_allowed_env([key]);
+        assert_eq!(fact.resolve(&ctx, &Scope::default()).unwrap(), "value");
+    }
+}
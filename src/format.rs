@@ -0,0 +1,283 @@
+use crate::error::{Error, Source};
+use crate::expr::quote;
+use crate::parse::{ParseOptions, parse_template};
+use crate::template::{Block, LoopSource, SectionMode, Template, Trim};
+use std::sync::Arc;
+
+/// Parse `source` and print it back in canonical form.
+///
+/// Control tags get single spaces inside their delimiters, matcher arguments
+/// are quoted consistently and operators are spaced as in `a && !b`. Text
+/// between tags, including whitespace that trim markers would remove, is
+/// kept as written, so the result renders exactly like the input.
+pub fn format_source(source: &Arc<Source>) -> Result<String, Error> {
+    let opts = ParseOptions {
+        lossless: true,
+        ..Default::default()
+    };
+    let (tpl, errors) = parse_template(source, opts);
+    Error::from_all(errors)?;
+    Ok(format_template(&tpl))
+}
+
+/// Print `tpl` as template source. Front matter is kept verbatim, and an
+/// `extends` tag is placed right after it.
+fn format_template(tpl: &Template) -> String {
+    let mut out = String::new();
+    if let Some(fm) = &tpl.front_matter {
+        out.push_str(&tpl.source.text[fm.span.start..fm.span.end]);
+    }
+    if let Some(ext) = &tpl.extends {
+        tag(&mut out, ext.trim, &format!("extends {}", quote(&ext.path)));
+    }
+    write_blocks(&mut out, &tpl.blocks);
+    out
+}
+
+fn write_blocks(out: &mut String, blocks: &[Block]) {
+    for block in blocks {
+        write_block(out, block);
+    }
+}
+
+fn write_block(out: &mut String, block: &Block) {
+    match block {
+        // An escaped tag opener is the only text that is exactly `<!--`;
+        // literal comments are kept verbatim. Other text was unescaped by
+        // the parser, so a literal `{{` needs its backslash back.
+        Block::Text(t) if t == "<!--" => out.push_str("\\<!--"),
+        Block::Text(t) if t.starts_with("<!--") => out.push_str(t),
+        Block::Text(t) => out.push_str(&t.replace("{{", "\\{{")),
+        Block::Raw { text, open, close } => {
+            tag(out, *open, "raw");
+            out.push_str(text);
+            tag(out, *close, "endraw");
+        }
+        Block::Interpolate { fact, .. } => out.push_str(&format!("{{{{ {fact} }}}}")),
+        Block::Comment { text, trim } => {
+            let body = if text.is_empty() {
+                "#".to_string()
+            } else {
+                format!("# {text}")
+            };
+            tag(out, *trim, &body);
+        }
+        Block::Include { path, trim, .. } => {
+            tag(out, *trim, &format!("include {}", quote(path)));
+        }
+        Block::If {
+            branches,
+            else_body,
+            else_trim,
+            end_trim,
+        } => {
+            for (i, b) in branches.iter().enumerate() {
+                let kw = if i == 0 { "if" } else { "elif" };
+                tag(out, b.trim, &format!("{kw} {}", b.cond));
+                write_blocks(out, &b.body);
+            }
+            if let Some(body) = else_body {
+                tag(out, *else_trim, "else");
+                write_blocks(out, body);
+            }
+            tag(out, *end_trim, "endif");
+        }
+        Block::Section {
+            name,
+            mode,
+            body,
+            open,
+            close,
+        } => {
+            let head = match mode {
+                SectionMode::Replace => format!("section {name}"),
+                SectionMode::Append => format!("section {name} append"),
+                SectionMode::Prepend => format!("section {name} prepend"),
+            };
+            tag(out, *open, &head);
+            write_blocks(out, body);
+            tag(out, *close, "endsection");
+        }
+        Block::Slot {
+            name,
+            body,
+            open,
+            close,
+            ..
+        } => {
+            tag(out, *open, &format!("block {name}"));
+            write_blocks(out, body);
+            tag(out, *close, "endblock");
+        }
+        Block::Define { def, trim } => {
+            tag(out, *trim, &format!("define {} = {}", def.name, def.expr));
+        }
+        Block::Once {
+            key,
+            body,
+            open,
+            close,
+        } => {
+            tag(out, *open, &format!("once {}", quote(key)));
+            write_blocks(out, body);
+            tag(out, *close, "endonce");
+        }
+        Block::Super(trim) => tag(out, *trim, "super"),
+        Block::For {
+            var,
+            source,
+            body,
+            open,
+            close,
+            ..
+        } => {
+            let source = match source {
+                LoopSource::Files(glob) => format!("files({})", quote(glob)),
+                LoopSource::Languages => "languages".to_string(),
+            };
+            tag(out, *open, &format!("for {var} in {source}"));
+            write_blocks(out, body);
+            tag(out, *close, "endfor");
+        }
+    }
+}
+
+/// Write a control tag with body `body` and trim markers `trim`.
+fn tag(out: &mut String, trim: Trim, body: &str) {
+    out.push_str(if trim.left { "<!--- " } else { "<!-- " });
+    out.push_str(body);
+    out.push_str(if trim.right { " --->" } else { " -->" });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn format(input: &str) -> Result<String, Error> {
+        format_source(&Arc::new(Source {
+            path: None,
+            text: input.to_string(),
+        }))
+    }
+
+    #[test]
+    fn formats_tags_canonically() {
+        let cases = [
+            ("plain text", "# Title\n\nbody\n", "# Title\n\nbody\n"),
+            (
+                "tag spacing",
+                "<!--if   env(CI)-->a<!--else-->b<!--   endif-->",
+                "<!-- if env(CI) -->a<!-- else -->b<!-- endif -->",
+            ),
+            (
+                "operators",
+                "<!-- if exists('a')&&!lang(\"rust\")||env( X = 1 ) -->x<!-- endif -->",
+                "<!-- if exists(\"a\") && !lang(rust) || env(X=1) -->x<!-- endif -->",
+            ),
+            (
+                "needed parentheses are kept",
+                "<!-- if ((env(A) || env(B))) && !(env(C) && env(D)) -->x<!-- endif -->",
+                "<!-- if (env(A) || env(B)) && !(env(C) && env(D)) -->x<!-- endif -->",
+            ),
+            (
+                "quoting",
+                "<!-- if lang(r\"C++\") && exists(r\"a\\b\") && env(\"X\"='a b') -->x<!-- endif -->",
+                "<!-- if lang(C++) && exists(\"a\\\\b\") && env(X=\"a b\") -->x<!-- endif -->",
+            ),
+            (
+                "trim markers and comments",
+                "<!---#  note  --->\n<!---include 'x.md'-->",
+                "<!--- # note --->\n<!--- include \"x.md\" -->",
+            ),
+            (
+                "sections, blocks and loops",
+                "<!--section a  append--><!--block b--><!--super--><!--endblock--><!--endsection-->\
+                 <!--for f in files( *.rs )-->{{f.dir}}<!--endfor-->",
+                "<!-- section a append --><!-- block b --><!-- super --><!-- endblock --><!-- endsection -->\
+                 <!-- for f in files(\"*.rs\") -->{{ f.dir }}<!-- endfor -->",
+            ),
+            (
+                "extends comes first",
+                "<!--extends base.md-->\n<!-- block a -->x<!-- endblock -->",
+                "<!-- extends \"base.md\" -->\n<!-- block a -->x<!-- endblock -->",
+            ),
+            (
+                "definitions",
+                "<!--define  ci=env( CI )--><!--define rust_ci = exists(Cargo.toml)&&ci-->\
+                 <!--if !rust_ci-->x<!--endif-->",
+                "<!-- define ci = env(CI) --><!-- define rust_ci = exists(\"Cargo.toml\") && ci -->\
+                 <!-- if !rust_ci -->x<!-- endif -->",
+            ),
+            (
+                "contains",
+                "<!--if contains( Cargo.toml,'[workspace]' )&&contains(\"*.rs\",r\"^fn\\s\" , regex)-->x<!--endif-->",
+                "<!-- if contains(\"Cargo.toml\", \"[workspace]\") && contains(\"*.rs\", \"^fn\\\\s\", regex) -->x<!-- endif -->",
+            ),
+            (
+                "dep",
+                "<!--if dep( cargo:tokio )||dep('npm:@types/node',dev)||dep(\"a b\")-->x<!--endif-->",
+                "<!-- if dep(cargo:tokio) || dep(npm:@types/node, dev) || dep(\"a b\") -->x<!-- endif -->",
+            ),
+            (
+                "cargo matchers",
+                "<!--if cargo_workspace ( )&&cargo_edition('2021')&&!cargo_feature( std )-->x<!--endif-->",
+                "<!-- if cargo_workspace() && cargo_edition(2021) && !cargo_feature(std) -->x<!-- endif -->",
+            ),
+            (
+                "front matter is kept",
+                "+++\npriority  =  1\n+++\n<!--if env(A)-->a<!--endif-->",
+                "+++\npriority  =  1\n+++\n<!-- if env(A) -->a<!-- endif -->",
+            ),
+            (
+                "code and escaped tags are kept",
+                "```md\n<!--if x-->\n```\n`<!--endif-->` \\<!-- if env(A) -->\n",
+                "```md\n<!--if x-->\n```\n`<!--endif-->` \\<!-- if env(A) -->\n",
+            ),
+            (
+                "once blocks",
+                "<!--once  fmt-->x<!--endonce-->",
+                "<!-- once \"fmt\" -->x<!-- endonce -->",
+            ),
+            (
+                "raw blocks are kept",
+                "<!--raw-->{{ x }} \\{{ <!-- if --><!--- endraw -->",
+                "<!-- raw -->{{ x }} \\{{ <!-- if --><!--- endraw -->",
+            ),
+            (
+                "escapes and literal comments",
+                "\\{{ x }} {{project.name}} <!-- {{ kept }} -->",
+                "\\{{ x }} {{ project.name }} <!-- {{ kept }} -->",
+            ),
+        ];
+        for (name, input, expect) in cases {
+            let got = format(input).unwrap();
+            assert_eq!(got, expect, "case: {name}");
+            assert_eq!(format(&got).unwrap(), got, "not idempotent: {name}");
+        }
+    }
+
+    #[test]
+    fn formatting_preserves_rendering() {
+        let input = "<!--if exists( 'Cargo.toml' )&&( lang(rust)||env(NOPE) )-->\n\
+                     rust\n<!---else--->\nother\n<!--endif-->\n\
+                     <!--for l in languages-->{{l}} <!--endfor-->\n";
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::write(td.path().join("Cargo.toml"), "").unwrap();
+        fs::write(td.path().join("main.rs"), "").unwrap();
+        let formatted = format(input).unwrap();
+        let before = Template::parse(input).unwrap().render(td.path()).unwrap();
+        let after = Template::parse(&formatted)
+            .unwrap()
+            .render(td.path())
+            .unwrap();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn refuses_templates_with_errors() {
+        assert!(format("<!-- if env(CI) -->").is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use crate::context::WalkOptions;
+use crate::error::{Error, Span};
+use std::path::{Component, Path};
+
+/// Template metadata from a front‑matter block at the very start of the
+/// file: TOML between `+++` lines or a YAML mapping between `---` lines. The
+/// block is never rendered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrontMatter {
+    /// Oldest agentsmd version that can render the template.
+    pub min_version: Option<String>,
+    pub description: Option<String>,
+    /// Files to write, relative to the project root and inside it.
+    pub outputs: Vec<String>,
+    /// Templates with a higher priority come first when combined.
+    pub priority: i64,
+    pub walk: WalkSettings,
+    /// The whole block, delimiters included.
+    pub span: Span,
+}
+
+/// Walk options set by front matter; unset fields fall through to other
+/// templates and then to the defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalkSettings {
+    pub hidden: Option<bool>,
+    pub gitignore: Option<bool>,
+    pub follow_links: Option<bool>,
+}
+
+impl WalkSettings {
+    /// These settings, with fields unset here taken from `other`.
+    pub fn or(self, other: WalkSettings) -> WalkSettings {
+        WalkSettings {
+            hidden: self.hidden.or(other.hidden),
+            gitignore: self.gitignore.or(other.gitignore),
+            follow_links: self.follow_links.or(other.follow_links),
+        }
+    }
+
+    /// `base` with the fields set here replaced.
+    pub fn apply(self, base: WalkOptions) -> WalkOptions {
+        WalkOptions {
+            hidden: self.hidden.unwrap_or(base.hidden),
+            gitignore: self.gitignore.unwrap_or(base.gitignore),
+            follow_links: self.follow_links.unwrap_or(base.follow_links),
+        }
+    }
+}
+
+/// Split front matter off the start of `text`. Returns `None` when `text`
+/// has none, and otherwise the offset at which the template body starts with
+/// the parsed metadata. A block that is opened but never closed is not
+/// front matter, and neither is a `---` block that isn't a YAML mapping: that
+/// is Markdown, such as a thematic break followed by text.
+pub fn split_front_matter(text: &str) -> Option<(usize, Result<FrontMatter, Error>)> {
+    let (fences, yaml): (&[&str], bool) = if text.starts_with("+++") {
+        (&["+++"], false)
+    } else if text.starts_with("---") {
+        (&["---", "..."], true)
+    } else {
+        return None;
+    };
+    let mut lines = text.split_inclusive('\n');
+    let first = lines.next()?;
+    if first.trim_end() != &text[..3] {
+        return None;
+    }
+    let mut pos = first.len();
+    let inner_start = pos;
+    for line in lines {
+        if fences.contains(&line.trim_end()) {
+            let inner = &text[inner_start..pos];
+            let table = if yaml {
+                yaml_table(inner)?
+            } else {
+                toml_table(inner)
+            };
+            let end = pos + line.len();
+            let span = Span::new(0, end);
+            let fm = table
+                .and_then(|t| parse(&t, span))
+                .map_err(|e| e.or_at(span));
+            return Some((end, fm));
+        }
+        pos += line.len();
+    }
+    None
+}
+
+fn toml_table(inner: &str) -> Result<toml::Table, Error> {
+    toml::from_str(inner).map_err(|e| Error::Template(format!("invalid TOML front matter: {e}")))
+}
+
+/// The keys of a YAML block, or `None` when it is not a mapping.
+fn yaml_table(inner: &str) -> Option<Result<toml::Table, Error>> {
+    let value: serde_norway::Value = serde_norway::from_str(inner).ok()?;
+    if !value.is_mapping() {
+        return None;
+    }
+    Some(
+        serde_norway::from_value(value)
+            .map_err(|e| Error::Template(format!("invalid YAML front matter: {e}"))),
+    )
+}
+
+fn parse(table: &toml::Table, span: Span) -> Result<FrontMatter, Error> {
+    let mut fm = FrontMatter {
+        span,
+        ..Default::default()
+    };
+    for (key, value) in table {
+        match key.as_str() {
+            "min_version" => {
+                let v = string(key, value)?;
+                check_version(&v)?;
+                fm.min_version = Some(v);
+            }
+            "description" => fm.description = Some(string(key, value)?),
+            "outputs" => {
+                fm.outputs = match value {
+                    toml::Value::String(s) => vec![s.clone()],
+                    toml::Value::Array(items) => items
+                        .iter()
+                        .map(|v| string(key, v))
+                        .collect::<Result<_, _>>()?,
+                    _ => return Err(type_error(key, "a string or a list of strings")),
+                };
+                for out in &fm.outputs {
+                    check_output(out)?;
+                }
+            }
+            "priority" => {
+                fm.priority = value
+                    .as_integer()
+                    .ok_or_else(|| type_error(key, "an integer"))?;
+            }
+            "walk" => {
+                let walk = value.as_table().ok_or_else(|| type_error(key, "a table"))?;
+                for (k, v) in walk {
+                    let flag = v
+                        .as_bool()
+                        .ok_or_else(|| type_error(&format!("walk.{k}"), "true or false"))?;
+                    match k.as_str() {
+                        "hidden" => fm.walk.hidden = Some(flag),
+                        "gitignore" => fm.walk.gitignore = Some(flag),
+                        "follow_links" => fm.walk.follow_links = Some(flag),
+                        _ => {
+                            return Err(Error::Template(format!(
+                                "unknown front matter key 'walk.{k}'"
+                            )));
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::Template(format!("unknown front matter key '{key}'")));
+            }
+        }
+    }
+    Ok(fm)
+}
+
+fn string(key: &str, value: &toml::Value) -> Result<String, Error> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| type_error(key, "a string"))
+}
+
+/// Fail unless `path` names a file inside the project root. Outputs are
+/// never tilde-expanded, but a leading `~` is rejected so it can't be
+/// mistaken for the home directory.
+fn check_output(path: &str) -> Result<(), Error> {
+    let mut parts = Path::new(path).components();
+    let inside = parts
+        .clone()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if path.starts_with('~') || !inside || !matches!(parts.next_back(), Some(Component::Normal(_)))
+    {
+        return Err(Error::Template(format!(
+            "output '{path}' must be a relative path inside the project root"
+        )));
+    }
+    Ok(())
+}
+
+fn type_error(key: &str, expected: &str) -> Error {
+    Error::Template(format!("front matter key '{key}' must be {expected}"))
+}
+
+/// Fail unless this build is at least version `min`.
+fn check_version(min: &str) -> Result<(), Error> {
+    check_version_against(min, env!("CARGO_PKG_VERSION"))
+}
+
+/// Fail unless version `have` is at least `min`. Missing trailing parts
+/// count as 0, so `0.0.2` satisfies `0.0.2.0`.
+fn check_version_against(min: &str, have: &str) -> Result<(), Error> {
+    let parse = |v: &str| -> Option<Vec<u64>> { v.split('.').map(|p| p.parse().ok()).collect() };
+    let Some(mut want) = parse(min.trim()) else {
+        return Err(Error::Template(format!(
+            "invalid min_version '{min}'; expected a version like 0.1.0"
+        )));
+    };
+    let mut have_parts = parse(have).unwrap_or_default();
+    let len = want.len().max(have_parts.len());
+    want.resize(len, 0);
+    have_parts.resize(len, 0);
+    if have_parts < want {
+        return Err(Error::Template(format!(
+            "template requires agentsmd {min} or newer; this is {have}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toml_and_yaml() {
+        let toml = "+++\ndescription = \"Rust\"\noutputs = [\"AGENTS.md\", \"CLAUDE.md\"]\n\
+                    priority = 5\n[walk]\nhidden = false\n+++\n# Body\n";
+        let yaml = "---\ndescription: Rust\noutputs: [AGENTS.md, CLAUDE.md]\npriority: 5\n\
+                    walk:\n  hidden: false\n---\n# Body\n";
+        for text in [toml, yaml] {
+            let (body, fm) = split_front_matter(text).unwrap();
+            assert_eq!(&text[body..], "# Body\n");
+            assert_eq!(
+                fm.unwrap(),
+                FrontMatter {
+                    min_version: None,
+                    description: Some("Rust".into()),
+                    outputs: vec!["AGENTS.md".into(), "CLAUDE.md".into()],
+                    priority: 5,
+                    walk: WalkSettings {
+                        hidden: Some(false),
+                        ..Default::default()
+                    },
+                    span: Span::new(0, body),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn only_a_closed_block_at_the_start_counts() {
+        for text in [
+            "# Title\n+++\n+++\n",
+            "---\nno closing fence\n",
+            "----\n---\n",
+            // `---` blocks that aren't YAML mappings are thematic breaks.
+            "---\n\nIntro text\n\n---\n\n# Title",
+            "---\n---\n",
+            "---\n[unclosed\n---\n",
+        ] {
+            assert!(split_front_matter(text).is_none(), "{text:?}");
+        }
+        let (body, fm) = split_front_matter("+++\n+++").unwrap();
+        assert_eq!(body, 7);
+        assert_eq!(fm.unwrap().priority, 0);
+    }
+
+    #[test]
+    fn compares_versions_with_different_lengths() {
+        for (min, have, ok) in [
+            ("0.0.2.0", "0.0.2", true),
+            ("0.0.2", "0.0.2.0", true),
+            ("0.1", "0.0.9", false),
+            ("0.0.2.1", "0.0.2", false),
+            ("1", "0.9.9", false),
+            ("0.9", "1.0.0", true),
+        ] {
+            assert_eq!(
+                check_version_against(min, have).is_ok(),
+                ok,
+                "{min} vs {have}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_metadata() {
+        let cases = [
+            (
+                "+++\nprority = 1\n+++\n",
+                "unknown front matter key 'prority'",
+            ),
+            (
+                "+++\npriority = \"high\"\n+++\n",
+                "'priority' must be an integer",
+            ),
+            (
+                "+++\n[walk]\nhiden = true\n+++\n",
+                "unknown front matter key 'walk.hiden'",
+            ),
+            (
+                "+++\nmin_version = \"999.0\"\n+++\n",
+                "requires agentsmd 999.0",
+            ),
+            ("+++\nmin_version = \"soon\"\n+++\n", "invalid min_version"),
+            ("---\npriority: ~\n---\n", "invalid YAML front matter"),
+            (
+                "+++\noutputs = [\"AGENTS.md\", \"../AGENTS.md\"]\n+++\n",
+                "output '../AGENTS.md' must be a relative path inside",
+            ),
+            (
+                "+++\noutputs = \"~/pwned.md\"\n+++\n",
+                "output '~/pwned.md' must be a relative path inside",
+            ),
+            (
+                "+++\noutputs = \"a/../../pwned.md\"\n+++\n",
+                "output 'a/../../pwned.md' must be a relative path inside",
+            ),
+            (
+                "---\noutputs: /etc/AGENTS.md\n---\n",
+                "output '/etc/AGENTS.md' must be a relative path inside",
+            ),
+            ("+++\noutputs = \".\"\n+++\n", "output '.' must be"),
+        ];
+        for (text, want) in cases {
+            let (_, fm) = split_front_matter(text).unwrap();
+            match fm.unwrap_err() {
+                Error::Located(d) => assert!(d.message.contains(want), "{}", d.message),
+                other => panic!("unexpected error: {other:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use crate::cargo::CargoProject;
+use crate::context::WalkOptions;
+use crate::deps::{self, Dependency, Ecosystem};
+use crate::error::Error;
+use crate::expr::{Matcher, compile_glob, content_regex, glob, lang_extensions};
+use globset::GlobSetBuilder;
+use ignore::{WalkBuilder, WalkState};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+
+/// Files larger than this are not searched by `contains()`.
+pub const MAX_CONTAINS_BYTES: u64 = 1 << 20;
+
+/// A file with a NUL byte in this many leading bytes is binary and is not
+/// searched by `contains()`.
+const BINARY_PROBE_BYTES: usize = 8 << 10;
+
+/// The non‑ignored files of a project, listed by one walk and shared by every
+/// matcher, loop and fact evaluated for it.
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    root: PathBuf,
+    /// Sorted by path.
+    files: Vec<IndexedFile>,
+    /// Lowercased extensions of all files, without the dot.
+    extensions: HashSet<String>,
+    /// Results of the file matchers evaluated so far.
+    results: Mutex<HashMap<Matcher, bool>>,
+    /// Dependencies from the project's manifests, once read.
+    deps: Mutex<Option<Arc<Vec<Dependency>>>>,
+    /// The project's Cargo manifests, once read.
+    cargo: Mutex<Option<Arc<CargoProject>>>,
+}
+
+#[derive(Debug)]
+struct IndexedFile {
+    /// Relative to the root, with `/` separators.
+    path: String,
+    /// Lowercased, without the dot.
+    ext: Option<String>,
+}
+
+impl IndexedFile {
+    fn new(root: &Path, path: &Path) -> Option<Self> {
+        let rel = path.strip_prefix(root).ok()?;
+        Some(Self {
+            path: rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            ext: rel
+                .extension()
+                .and_then(|s| s.to_str())
+                .map(str::to_ascii_lowercase),
+        })
+    }
+}
+
+impl FileIndex {
+    /// Walk `root` once with `threads` threads (0 for one per CPU) and
+    /// index every file found. Files arrive in no particular order and are
+    /// sorted, so the index does not depend on the thread count.
+    pub fn build(root: &Path, opts: &WalkOptions, threads: usize) -> Self {
+        let (tx, rx) = mpsc::channel();
+        walker(root, opts)
+            .threads(threads)
+            .build_parallel()
+            .run(|| {
+                let tx = tx.clone();
+                Box::new(move |dent| {
+                    if let Ok(dent) = dent
+                        && dent.file_type().is_some_and(|t| t.is_file())
+                        && let Some(file) = IndexedFile::new(root, dent.path())
+                    {
+                        let _ = tx.send(file);
+                    }
+                    WalkState::Continue
+                })
+            });
+        drop(tx);
+        let mut files: Vec<IndexedFile> = rx.into_iter().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let extensions = files.iter().filter_map(|f| f.ext.clone()).collect();
+        Self {
+            root: root.to_path_buf(),
+            files,
+            extensions,
+            results: Mutex::default(),
+            deps: Mutex::default(),
+            cargo: Mutex::default(),
+        }
+    }
+
+    /// The project root the paths are relative to.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Paths of all files, sorted.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|f| f.path.as_str())
+    }
+
+    /// Whether `path`, relative to the root with `/` separators, is a file.
+    pub fn has(&self, path: &str) -> bool {
+        self.files
+            .binary_search_by(|f| f.path.as_str().cmp(path))
+            .is_ok()
+    }
+
+    /// Paths of the files matching `pattern`, sorted.
+    pub fn matching(&self, pattern: &str) -> Result<Vec<String>, Error> {
+        let gs = compile_glob("files", pattern)?;
+        Ok(self
+            .files
+            .iter()
+            .filter(|f| gs.is_match(&f.path))
+            .map(|f| f.path.clone())
+            .collect())
+    }
+
+    /// Whether any file matches `pattern`.
+    pub fn exists(&self, pattern: &str) -> Result<bool, Error> {
+        self.memoized(Matcher::Exists(pattern.to_string()), || {
+            let gs = compile_glob("exists", pattern)?;
+            Ok(self.files.iter().any(|f| gs.is_match(&f.path)))
+        })
+    }
+
+    /// Whether any file has an extension of language `name`.
+    pub fn lang(&self, name: &str) -> Result<bool, Error> {
+        self.memoized(Matcher::Lang(name.to_string()), || {
+            let exts = lang_extensions(name)?;
+            Ok(!self.extensions.is_disjoint(&exts))
+        })
+    }
+
+    /// Whether a file matching `glob` holds `pattern`, as a regular
+    /// expression when `regex` is set. Files over [`MAX_CONTAINS_BYTES`],
+    /// binary files and files that cannot be read are skipped.
+    pub fn contains(&self, glob: &str, pattern: &str, regex: bool) -> Result<bool, Error> {
+        let key = Matcher::Contains {
+            glob: glob.to_string(),
+            pattern: pattern.to_string(),
+            regex,
+        };
+        self.memoized(key, || {
+            let gs = compile_glob("contains", glob)?;
+            let re = content_regex(pattern, regex)?;
+            Ok(self
+                .files
+                .iter()
+                .filter(|f| gs.is_match(&f.path))
+                .filter_map(|f| read_text(&self.root.join(&f.path)))
+                .any(|text| re.is_match(&text)))
+        })
+    }
+
+    /// Whether a manifest declares package `name`, only in ecosystem `eco`
+    /// when given and only as a development dependency when `dev` is set.
+    /// Manifests are read on first use.
+    pub fn dep(&self, eco: Option<Ecosystem>, name: &str, dev: bool) -> Result<bool, Error> {
+        let key = Matcher::Dep {
+            eco,
+            name: name.to_string(),
+            dev,
+        };
+        self.memoized(key, || {
+            let deps = self.dependencies()?;
+            Ok(deps
+                .iter()
+                .any(|d| eco.is_none_or(|e| e == d.eco) && (d.dev || !dev) && d.is(name)))
+        })
+    }
+
+    fn dependencies(&self) -> Result<Arc<Vec<Dependency>>, Error> {
+        let mut deps = self.deps.lock().unwrap();
+        if let Some(d) = &*deps {
+            return Ok(d.clone());
+        }
+        let d = Arc::new(deps::collect(self)?);
+        *deps = Some(d.clone());
+        Ok(d)
+    }
+
+    /// What the project's `Cargo.toml` files declare, read on first use.
+    pub fn cargo(&self) -> Result<Arc<CargoProject>, Error> {
+        let mut cargo = self.cargo.lock().unwrap();
+        if let Some(c) = &*cargo {
+            return Ok(c.clone());
+        }
+        let c = Arc::new(CargoProject::load(self)?);
+        *cargo = Some(c.clone());
+        Ok(c)
+    }
+
+    /// Evaluate many `exists` patterns with one glob set and one pass over
+    /// the files, so later calls to [`FileIndex::exists`] are lookups.
+    /// Invalid patterns are skipped; they fail when evaluated.
+    pub fn prime_exists<'a>(&self, patterns: impl IntoIterator<Item = &'a str>) {
+        let mut results = self.results.lock().unwrap();
+        let mut todo = Vec::new();
+        let mut gsb = GlobSetBuilder::new();
+        for p in patterns {
+            let key = Matcher::Exists(p.to_string());
+            if results.contains_key(&key) || todo.contains(&key) {
+                continue;
+            }
+            if let Ok(g) = glob("exists", p) {
+                gsb.add(g);
+                todo.push(key);
+            }
+        }
+        if todo.is_empty() {
+            return;
+        }
+        let Ok(gs) = gsb.build() else {
+            return;
+        };
+        let mut hit = vec![false; todo.len()];
+        for f in &self.files {
+            for i in gs.matches(&f.path) {
+                hit[i] = true;
+            }
+        }
+        results.extend(todo.into_iter().zip(hit));
+    }
+
+    /// Names of programming languages with at least one file, sorted.
+    pub fn languages(&self) -> Vec<String> {
+        self.extensions
+            .iter()
+            .filter_map(|e| languages::from_extension(e))
+            .filter(|l| l.language_type == "programming")
+            .map(|l| l.name.to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn memoized(
+        &self,
+        key: Matcher,
+        eval: impl FnOnce() -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        if let Some(&v) = self.results.lock().unwrap().get(&key) {
+            return Ok(v);
+        }
+        let v = eval()?;
+        self.results.lock().unwrap().insert(key, v);
+        Ok(v)
+    }
+}
+
+/// The contents of the file at `path`, unless it is too large, binary or
+/// unreadable.
+fn read_text(path: &Path) -> Option<Vec<u8>> {
+    let file = File::open(path).ok()?;
+    if file.metadata().ok()?.len() > MAX_CONTAINS_BYTES {
+        return None;
+    }
+    let mut buf = Vec::new();
+    file.take(MAX_CONTAINS_BYTES).read_to_end(&mut buf).ok()?;
+    let probe = &buf[..buf.len().min(BINARY_PROBE_BYTES)];
+    if probe.contains(&0) {
+        return None;
+    }
+    Some(buf)
+}
+
+/// A walk over all non‑ignored entries under `root`.
+///
+/// By default hidden files are included; `.gitignore`, `.ignore` and git
+/// excludes are respected; symlinks are not followed.
+fn walker(root: &Path, opts: &WalkOptions) -> WalkBuilder {
+    let mut wb = WalkBuilder::new(root);
+    wb.hidden(!opts.hidden)
+        .parents(false)
+        .follow_links(opts.follow_links)
+        .ignore(opts.gitignore)
+        .git_ignore(opts.gitignore)
+        .git_exclude(opts.gitignore)
+        .git_global(opts.gitignore);
+    wb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn index(files: &[&str]) -> (TempDir, FileIndex) {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        for f in files {
+            let path = td.path().join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let idx = FileIndex::build(td.path(), &WalkOptions::default(), 0);
+        (td, idx)
+    }
+
+    #[test]
+    fn answers_matchers_from_one_walk() {
+        let (td, idx) = index(&["src/main.rs", "lib/util.PY", "README.md"]);
+        idx.prime_exists(["**/*.rs", "docs/**", "[bad", "**/*.rs"]);
+        // Files added after the walk are not seen.
+        fs::write(td.path().join("docs.md"), "").unwrap();
+        let cases = [
+            ("primed hit", idx.exists("**/*.rs"), true),
+            ("primed miss", idx.exists("docs/**"), false),
+            ("unprimed", idx.exists("*.md"), true),
+            ("new file", idx.exists("docs.md"), false),
+            ("lang", idx.lang("rust"), true),
+            ("lang by uppercase extension", idx.lang("python"), true),
+            ("absent lang", idx.lang("go"), false),
+        ];
+        for (name, got, want) in cases {
+            assert_eq!(got.unwrap(), want, "case: {name}");
+        }
+        assert!(idx.exists("[bad").is_err());
+        assert!(idx.lang("nope").is_err());
+        assert_eq!(idx.languages(), ["Python", "Rust"]);
+        assert_eq!(idx.matching("*.md").unwrap(), ["README.md"]);
+        assert_eq!(idx.matching("**/*").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_index() {
+        let names: Vec<String> = (0..50).map(|i| format!("d{}/f{i}.rs", i % 7)).collect();
+        let (td, idx) = index(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        let all = idx.matching("**").unwrap();
+        assert_eq!(all.len(), 50);
+        assert!(all.is_sorted());
+        for threads in [1, 2, 8] {
+            let other = FileIndex::build(td.path(), &WalkOptions::default(), threads);
+            assert_eq!(other.matching("**").unwrap(), all, "threads: {threads}");
+        }
+    }
+
+    #[test]
+    fn contexts_share_an_index_per_walk() {
+        let (td, _) = index(&["a.rs"]);
+        let ctx = Context::new(td.path());
+        let first = ctx.files();
+        assert!(Arc::ptr_eq(&first, &ctx.clone().files()));
+        let mut hidden = ctx.clone();
+        hidden.walk.hidden = false;
+        assert!(!Arc::ptr_eq(&first, &hidden.files()));
+        assert!(!Arc::ptr_eq(&first, &Context::new(td.path()).files()));
+    }
+}
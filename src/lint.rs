@@ -0,0 +1,330 @@
+use crate::error::Span;
+use crate::expr::{Expr, Matcher, lang_extensions};
+use crate::template::{Block, LoopSource, Template};
+
+/// Most distinct matchers a condition may hold for its truth table to be
+/// checked; larger conditions are not analysed.
+const MAX_ATOMS: usize = 12;
+
+/// A likely mistake in a template: valid syntax that cannot do what its
+/// author meant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    /// Stable kebab‑case identifier for the kind of problem.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+/// Check `tpl` for template smells. Only the template itself is examined, so
+/// no project is needed; results are sorted by position.
+pub fn lint(tpl: &Template) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    lint_blocks(&tpl.blocks, &mut lints);
+    lints.sort_by_key(|l| l.span.start);
+    lints
+}
+
+fn lint_blocks(blocks: &[Block], lints: &mut Vec<Lint>) {
+    // Conditions of the `if` blocks seen so far at this level.
+    let mut siblings: Vec<String> = Vec::new();
+    for block in blocks {
+        match block {
+            Block::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                let mut arms: Vec<String> = Vec::new();
+                for (i, b) in branches.iter().enumerate() {
+                    let cond = b.cond.to_string();
+                    let span = b.cond.span();
+                    lint_expr(&b.cond, lints);
+                    if arms.contains(&cond) {
+                        lints.push(Lint {
+                            code: "duplicate-condition",
+                            message: format!(
+                                "condition `{cond}` repeats an earlier arm, so this arm is never taken"
+                            ),
+                            span,
+                        });
+                    } else if i == 0 && siblings.contains(&cond) {
+                        lints.push(Lint {
+                            code: "duplicate-condition",
+                            message: format!(
+                                "condition `{cond}` repeats an earlier `if` at this level; consider merging them"
+                            ),
+                            span,
+                        });
+                    }
+                    if is_blank(&b.body) {
+                        lints.push(Lint {
+                            code: "empty-branch",
+                            message: format!("branch `{cond}` has an empty body"),
+                            span,
+                        });
+                    }
+                    arms.push(cond);
+                    lint_blocks(&b.body, lints);
+                }
+                if let Some(first) = branches.first() {
+                    siblings.push(first.cond.to_string());
+                }
+                if let Some(body) = else_body {
+                    lint_blocks(body, lints);
+                }
+            }
+            Block::Define { def, .. } => lint_expr(&def.expr, lints),
+            Block::Section { body, .. } | Block::Slot { body, .. } | Block::Once { body, .. } => {
+                lint_blocks(body, lints)
+            }
+            Block::For {
+                source, body, span, ..
+            } => {
+                if let LoopSource::Files(glob) = source {
+                    lint_glob("files", glob, *span, lints);
+                }
+                lint_blocks(body, lints);
+            }
+            Block::Text(_)
+            | Block::Raw { .. }
+            | Block::Interpolate { .. }
+            | Block::Comment { .. }
+            | Block::Include { .. }
+            | Block::Super(_) => {}
+        }
+    }
+}
+
+/// Whether `blocks` would render nothing but whitespace.
+fn is_blank(blocks: &[Block]) -> bool {
+    blocks.iter().all(|b| match b {
+        Block::Text(t) | Block::Raw { text: t, .. } => t.trim().is_empty(),
+        Block::Comment { .. } | Block::Define { .. } => true,
+        _ => false,
+    })
+}
+
+fn lint_expr(expr: &Expr, lints: &mut Vec<Lint>) {
+    // Matchers inside a `define` are checked once, at the definition.
+    let mut own = Vec::new();
+    collect_atoms(expr, false, &mut own);
+    for (m, span) in &own {
+        match m {
+            Matcher::Exists(glob) => lint_glob("exists", glob, *span, lints),
+            Matcher::Contains { glob, .. } => lint_glob("contains", glob, *span, lints),
+            Matcher::Lang(name)
+                if !name.contains("{{") && lang_extensions(name).is_ok_and(|e| e.is_empty()) =>
+            {
+                lints.push(Lint {
+                    code: "lang-without-extensions",
+                    message: format!(
+                        "language `{name}` has no file extensions, so lang() is always false"
+                    ),
+                    span: *span,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut atoms = Vec::new();
+    collect_atoms(expr, true, &mut atoms);
+    let mut distinct: Vec<&Matcher> = Vec::new();
+    for (m, _) in &atoms {
+        if !distinct.contains(m) {
+            distinct.push(m);
+        }
+    }
+    if distinct.len() > MAX_ATOMS {
+        return;
+    }
+    // Matchers are treated as independent, which can only add assignments
+    // that are impossible in practice, so both verdicts below are sound.
+    let outcomes: Vec<bool> = (0u32..1 << distinct.len())
+        .map(|bits| {
+            eval(expr, &|m| {
+                let i = distinct.iter().position(|d| *d == m).unwrap_or(0);
+                bits & (1 << i) != 0
+            })
+        })
+        .collect();
+    let (code, verdict) = if outcomes.iter().all(|o| !o) {
+        ("never-true", "can never be true")
+    } else if outcomes.iter().all(|o| *o) {
+        ("always-true", "is always true")
+    } else {
+        return;
+    };
+    lints.push(Lint {
+        code,
+        message: format!("condition `{expr}` {verdict}"),
+        span: expr.span(),
+    });
+}
+
+/// The matchers in `expr`; with `expand`, also those of the definitions it
+/// refers to.
+fn collect_atoms<'a>(expr: &'a Expr, expand: bool, out: &mut Vec<(&'a Matcher, Span)>) {
+    match expr {
+        Expr::Matcher(m, span) => out.push((m, *span)),
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            collect_atoms(a, expand, out);
+            collect_atoms(b, expand, out);
+        }
+        Expr::Not(e) => collect_atoms(e, expand, out),
+        Expr::Ref(def, _) if expand => collect_atoms(&def.expr, expand, out),
+        Expr::Ref(..) => {}
+    }
+}
+
+fn eval(expr: &Expr, value: &dyn Fn(&Matcher) -> bool) -> bool {
+    match expr {
+        Expr::Matcher(m, _) => value(m),
+        Expr::And(a, b) => eval(a, value) && eval(b, value),
+        Expr::Or(a, b) => eval(a, value) || eval(b, value),
+        Expr::Not(e) => !eval(e, value),
+        Expr::Ref(def, _) => eval(&def.expr, value),
+    }
+}
+
+/// Flag glob patterns that cannot match any file: only files are matched,
+/// by their path relative to the project root.
+fn lint_glob(matcher: &str, glob: &str, span: Span, lints: &mut Vec<Lint>) {
+    if glob.contains("{{") {
+        return;
+    }
+    let last = glob.rsplit('/').next().unwrap_or(glob);
+    let reason = if glob.ends_with('/') {
+        "ends with '/', but directories never match"
+    } else if last == "." || last == ".." {
+        "names a directory, but directories never match"
+    } else if glob.starts_with('/') {
+        "starts with '/', but paths are matched relative to the project root"
+    } else {
+        return;
+    };
+    lints.push(Lint {
+        code: "unmatchable-glob",
+        message: format!("{matcher}() pattern `{glob}` {reason}"),
+        span,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, Source};
+    use crate::parse::{ParseOptions, parse_template};
+    use std::sync::Arc;
+
+    fn lints(input: &str) -> Vec<(&'static str, String)> {
+        let source = Arc::new(Source {
+            path: None,
+            text: input.to_string(),
+        });
+        let (tpl, errors) = parse_template(&source, ParseOptions::default());
+        Error::from_all(errors).unwrap();
+        lint(&tpl)
+            .into_iter()
+            .map(|l| (l.code, input[l.span.start..l.span.end].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn flags_template_smells() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            /// Lint codes with the text their spans cover.
+            expect: &'static [(&'static str, &'static str)],
+        }
+        let cases = vec![
+            Case {
+                name: "clean",
+                input: "<!-- if lang(rust) -->a<!-- elif env(CI) -->b<!-- endif -->",
+                expect: &[],
+            },
+            Case {
+                name: "clean template using every kind of block",
+                input: "# {{ project.name }}\n\
+                 <!-- define ci = env(CI) -->\n\
+                 <!-- section testing -->\n\
+                 <!-- if exists(\"Cargo.toml\") && !ci -->Run cargo test.\n<!-- else -->Run make.\n<!-- endif -->\n\
+                 <!-- endsection -->\n\
+                 <!-- for f in files(\"crates/*/Cargo.toml\") -->- {{ f.dir }}\n<!-- endfor -->\n\
+                 <!-- once \"style\" --><!-- if contains(\"*.rs\", \"unsafe\") -->Audit unsafe.<!-- endif --><!-- endonce -->\n",
+                expect: &[],
+            },
+            Case {
+                name: "contradiction",
+                input: "<!-- if exists(x) && !exists(x) -->a<!-- endif -->",
+                expect: &[("never-true", "exists(x) && !exists(x)")],
+            },
+            Case {
+                name: "tautology",
+                input: "<!-- if env(A) || !env(A) -->a<!-- endif -->",
+                expect: &[("always-true", "env(A) || !env(A)")],
+            },
+            Case {
+                name: "empty bodies",
+                input: "<!-- if env(A) -->\n  <!-- # todo -->\n<!-- elif env(B) --><!-- else -->x<!-- endif -->",
+                expect: &[("empty-branch", "env(A)"), ("empty-branch", "env(B)")],
+            },
+            Case {
+                name: "repeated arm",
+                input: "<!-- if env(A) -->a<!-- elif env( A ) -->b<!-- endif -->",
+                expect: &[("duplicate-condition", "env( A )")],
+            },
+            Case {
+                name: "repeated sibling",
+                input: "<!-- if env(A) -->a<!-- endif -->\n<!-- if env(B) -->b<!-- endif -->\n\
+                 <!-- if env(A) -->c<!-- endif -->",
+                expect: &[("duplicate-condition", "env(A)")],
+            },
+            Case {
+                name: "nested blocks are separate levels",
+                input: "<!-- if env(A) --><!-- if env(A) -->a<!-- endif --><!-- endif -->",
+                expect: &[],
+            },
+            Case {
+                name: "language without extensions",
+                input: "<!-- if lang(\"Go Module\") -->a<!-- endif -->",
+                expect: &[("lang-without-extensions", "lang(\"Go Module\")")],
+            },
+            Case {
+                name: "unmatchable glob",
+                input: "<!-- if exists(\"src/\") -->a<!-- endif -->",
+                expect: &[("unmatchable-glob", "exists(\"src/\")")],
+            },
+            Case {
+                name: "every unmatchable glob form",
+                input: "<!-- if exists(\"src/\") || exists(\"/Cargo.toml\") || exists(\"a/.\") -->a<!-- endif -->\
+                 <!-- for f in files(\"docs/\") -->{{ f }}<!-- endfor -->",
+                expect: &[
+                    ("unmatchable-glob", "exists(\"src/\")"),
+                    ("unmatchable-glob", "exists(\"/Cargo.toml\")"),
+                    ("unmatchable-glob", "exists(\"a/.\")"),
+                    ("unmatchable-glob", "<!-- for f in files(\"docs/\") -->"),
+                ],
+            },
+            Case {
+                name: "definitions are checked where defined and expanded where used",
+                input: "<!-- define docs = exists(\"docs/\") --><!-- define ci = env(CI) -->\
+                 <!-- if ci && !ci -->a<!-- endif --><!-- if docs -->b<!-- endif -->",
+                expect: &[
+                    ("unmatchable-glob", "exists(\"docs/\")"),
+                    ("never-true", "ci && !ci"),
+                ],
+            },
+        ];
+        for c in cases {
+            let expect: Vec<(&str, String)> = c
+                .expect
+                .iter()
+                .map(|(code, s)| (*code, s.to_string()))
+                .collect();
+            assert_eq!(lints(c.input), expect, "case: {}", c.name);
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use crate::error::{Error, Source, Span};
+use crate::parse::{ParseOptions, parse_template};
+use crate::template::{Block, Template, find_slot};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Expand a leading `~` in `p` to the current user's home directory.
+pub fn expand_tilde(p: &Path) -> PathBuf {
+    let s = p.to_string_lossy();
+    PathBuf::from(shellexpand::tilde(&s).into_owned())
+}
+
+/// Read and parse the template at `path`, resolving `include` and `extends`
+/// directives.
+///
+/// Include and extends paths are tilde‑expanded and, when relative, resolved
+/// against the directory of the referencing file. Referenced files are parsed
+/// as full templates, so their own conditionals, includes and parents work as
+/// usual, and every file is parsed with the same `opts`.
+pub fn load_template(path: &Path, opts: ParseOptions) -> Result<Template, Error> {
+    let txt = fs::read_to_string(path)
+        .map_err(|e| Error::Root(format!("template read error ({}): {e}", path.display())))?;
+    let mut chain = vec![path.to_path_buf()];
+    let mut errors = Vec::new();
+    let tpl = parse_with_includes(txt, &mut chain, opts, &mut errors);
+    Error::from_all(errors).map(|()| tpl)
+}
+
+/// How one template file refers to another.
+#[derive(Clone, Copy)]
+enum Relation {
+    Include,
+    Extends,
+}
+
+impl Relation {
+    fn describe(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Relation::Include => ("include", "included template", "included"),
+            Relation::Extends => ("extends", "parent template", "extended"),
+        }
+    }
+}
+
+/// Parse `txt` (the contents of the last file in `chain`) and recursively
+/// load its includes and parents. `chain` holds the files that led here,
+/// outermost first. Errors are pushed onto `errors` and loading carries on,
+/// so one run reports problems from every file.
+fn parse_with_includes(
+    txt: String,
+    chain: &mut Vec<PathBuf>,
+    opts: ParseOptions,
+    errors: &mut Vec<Error>,
+) -> Template {
+    let source = Source {
+        path: chain.last().cloned(),
+        text: txt,
+    };
+    let (mut tpl, parse_errors) = parse_template(&Arc::new(source), opts);
+    errors.extend(parse_errors);
+    resolve_includes(&mut tpl.blocks, &tpl.source, chain, opts, errors);
+    if let Some(ext) = &mut tpl.extends {
+        let at = (ext.span, &tpl.source);
+        match load_related(Relation::Extends, &ext.path, at, chain, opts, errors) {
+            Ok(parent) => {
+                let mut slots = Vec::new();
+                slot_names(&tpl.blocks, &tpl.source, &mut slots);
+                for (name, span, source) in slots {
+                    if !defined_in_chain(&parent, name) {
+                        let msg = format!(
+                            "block '{name}' is not defined by parent template {}; extended from {}",
+                            ext.path,
+                            format_chain(chain)
+                        );
+                        errors.push(Error::at(span, msg).in_source(source));
+                    }
+                }
+                ext.parent = Some(Box::new(parent));
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    tpl
+}
+
+/// Load the template that `arg`, in the tag at `at`, refers to from the last
+/// file in `chain`. Only a cycle or an unreadable file is returned as an
+/// error, located at the tag; problems inside the file go to `errors`.
+fn load_related(
+    rel: Relation,
+    arg: &str,
+    at: (Span, &Arc<Source>),
+    chain: &mut Vec<PathBuf>,
+    opts: ParseOptions,
+    errors: &mut Vec<Error>,
+) -> Result<Template, Error> {
+    let (tag, noun, verb) = rel.describe();
+    let (span, source) = at;
+    let target = include_path(chain.last().map(PathBuf::as_path), arg);
+    if chain.iter().any(|p| paths_equal(p, &target)) {
+        chain.push(target);
+        let msg = format!("{tag} cycle: {}", format_chain(chain));
+        chain.pop();
+        return Err(Error::at(span, msg).in_source(source));
+    }
+    let txt = fs::read_to_string(&target).map_err(|e| {
+        let msg = format!(
+            "cannot read {noun} {} ({e}); {verb} from {}",
+            target.display(),
+            format_chain(chain)
+        );
+        Error::at(span, msg).in_source(source)
+    })?;
+    chain.push(target);
+    let tpl = parse_with_includes(txt, chain, opts, errors);
+    chain.pop();
+    Ok(tpl)
+}
+
+fn resolve_includes(
+    blocks: &mut [Block],
+    source: &Arc<Source>,
+    chain: &mut Vec<PathBuf>,
+    opts: ParseOptions,
+    errors: &mut Vec<Error>,
+) {
+    for b in blocks {
+        match b {
+            Block::Text(_)
+            | Block::Interpolate { .. }
+            | Block::Comment { .. }
+            | Block::Define { .. }
+            | Block::Raw { .. }
+            | Block::Super(_) => {}
+            Block::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for br in branches {
+                    resolve_includes(&mut br.body, source, chain, opts, errors);
+                }
+                if let Some(body) = else_body {
+                    resolve_includes(body, source, chain, opts, errors);
+                }
+            }
+            Block::Section { body, .. }
+            | Block::Slot { body, .. }
+            | Block::Once { body, .. }
+            | Block::For { body, .. } => resolve_includes(body, source, chain, opts, errors),
+            Block::Include {
+                path,
+                template,
+                span,
+                ..
+            } => {
+                let at = (*span, source);
+                match load_related(Relation::Include, path, at, chain, opts, errors) {
+                    Ok(tpl) => *template = Some(tpl),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+    }
+}
+
+/// Collect the names and tags of all blocks defined within `blocks`, which
+/// were parsed from `source`, with the source of each tag.
+fn slot_names<'a>(
+    blocks: &'a [Block],
+    source: &'a Arc<Source>,
+    out: &mut Vec<(&'a str, Span, &'a Arc<Source>)>,
+) {
+    for b in blocks {
+        match b {
+            Block::Text(_)
+            | Block::Interpolate { .. }
+            | Block::Comment { .. }
+            | Block::Define { .. }
+            | Block::Raw { .. }
+            | Block::Super(_) => {}
+            Block::Slot {
+                name, body, span, ..
+            } => {
+                out.push((name, *span, source));
+                slot_names(body, source, out);
+            }
+            Block::Section { body, .. } | Block::Once { body, .. } | Block::For { body, .. } => {
+                slot_names(body, source, out)
+            }
+            Block::Include { template, .. } => {
+                if let Some(t) = template {
+                    slot_names(&t.blocks, &t.source, out);
+                }
+            }
+            Block::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for br in branches {
+                    slot_names(&br.body, source, out);
+                }
+                if let Some(body) = else_body {
+                    slot_names(body, source, out);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `tpl` or one of its ancestors defines block `name`.
+fn defined_in_chain(tpl: &Template, name: &str) -> bool {
+    let mut cur = Some(tpl);
+    while let Some(t) = cur {
+        if find_slot(t, name).is_some() {
+            return true;
+        }
+        cur = t.extends.as_ref().and_then(|e| e.parent.as_deref());
+    }
+    false
+}
+
+/// Resolve an include or extends argument relative to the referencing file.
+fn include_path(including: Option<&Path>, arg: &str) -> PathBuf {
+    let p = expand_tilde(Path::new(arg));
+    if p.is_absolute() {
+        return p;
+    }
+    match including.and_then(Path::parent) {
+        Some(dir) => dir.join(p),
+        None => p,
+    }
+}
+
+/// Whether `a` and `b` name the same file, canonicalizing each path that
+/// exists and comparing the others as given.
+pub fn paths_equal(a: &Path, b: &Path) -> bool {
+    let a_abs = a.canonicalize().unwrap_or_else(|_| a.to_path_buf());
+    let b_abs = b.canonicalize().unwrap_or_else(|_| b.to_path_buf());
+    a_abs == b_abs
+}
+
+fn format_chain(chain: &[PathBuf]) -> String {
+    chain
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::EnvGuard;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, contents: &str) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+
+    fn setup() -> TempDir {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        td
+    }
+
+    #[test]
+    fn includes_are_relative_to_including_file() {
+        let td = setup();
+        let root = td.path();
+        write(
+            &root.join("main.md"),
+            "A\n<!-- include \"parts/rust.md\" -->B\n",
+        );
+        write(&root.join("parts/rust.md"), "R\n<!-- include 'git.md' -->");
+        write(&root.join("parts/git.md"), "G\n");
+        let tpl = load_template(&root.join("main.md"), ParseOptions::default()).unwrap();
+        assert_eq!(tpl.render(root).unwrap(), "A\nR\nG\nB\n");
+    }
+
+    #[test]
+    fn included_conditionals_are_evaluated() {
+        let td = setup();
+        let root = td.path();
+        write(&root.join("main.md"), "<!-- include \"frag.md\" -->");
+        write(
+            &root.join("frag.md"),
+            "<!-- if exists(\"Cargo.toml\") -->rust<!-- else -->other<!-- endif -->",
+        );
+        let tpl = load_template(&root.join("main.md"), ParseOptions::default()).unwrap();
+        assert_eq!(tpl.render(root).unwrap(), "other");
+        write(&root.join("Cargo.toml"), "");
+        assert_eq!(tpl.render(root).unwrap(), "rust");
+    }
+
+    #[test]
+    fn errors_name_the_file_they_occur_in() {
+        let td = setup();
+        let root = td.path();
+        write(&root.join("main.md"), "A\n<!-- include \"frag.md\" -->");
+        write(
+            &root.join("frag.md"),
+            "x\n<!-- if lang(klingon) -->k<!-- endif -->",
+        );
+        let msg = load_template(&root.join("main.md"), ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("unknown language: klingon"), "{msg}");
+        assert!(msg.contains("frag.md:2:9"), "{msg}");
+
+        write(&root.join("frag.md"), "x\n<!-- endif -->");
+        let msg = load_template(&root.join("main.md"), ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("frag.md:2:1"), "{msg}");
+    }
+
+    #[test]
+    fn loading_continues_past_errors() {
+        let td = setup();
+        let root = td.path();
+        write(
+            &root.join("main.md"),
+            "<!-- include \"missing.md\" -->\n<!-- include \"frag.md\" -->\n<!-- endsection -->",
+        );
+        write(&root.join("frag.md"), "{{ nope }}");
+        let msg = load_template(&root.join("main.md"), ParseOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("stray 'endsection'"), "{msg}");
+        assert!(msg.contains("unknown fact: nope"), "{msg}");
+        assert!(msg.contains("cannot read included template"), "{msg}");
+        assert!(msg.ends_with("3 template errors"), "{msg}");
+    }
+
+    #[test]
+    fn extends_fills_parent_blocks() {
+        let td = setup();
+        let root = td.path();
+        write(
+            &root.join("base/base.md"),
+            "# Agents\n<!-- block intro -->Base intro\n<!-- endblock -->\
+             <!-- block testing -->Run tests\n<!-- endblock -->",
+        );
+        write(
+            &root.join("base/mid.md"),
+            "<!-- extends \"base.md\" -->\n<!-- block testing --><!-- super -->Also lint\n<!-- endblock -->",
+        );
+        write(
+            &root.join("child.md"),
+            "<!-- extends \"base/mid.md\" -->\nignored outside blocks\n\
+             <!-- block intro -->Child intro\n<!-- endblock -->\
+             <!-- block testing --><!-- super -->And fmt\n<!-- endblock -->",
+        );
+        let tpl = load_template(&root.join("child.md"), ParseOptions::default()).unwrap();
+        assert_eq!(
+            tpl.render(root).unwrap(),
+            "# Agents\nChild intro\nRun tests\nAlso lint\nAnd fmt\n"
+        );
+    }
+
+    #[test]
+    fn extends_errors_name_chain() {
+        let td = setup();
+        let root = td.path();
+        write(&root.join("a.md"), "<!-- extends \"b.md\" -->");
+        write(&root.join("b.md"), "<!-- extends \"a.md\" -->");
+        let err = load_template(&root.join("a.md"), ParseOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("extends cycle"), "{err}");
+
+        write(&root.join("c.md"), "\n<!-- extends \"missing.md\" -->");
+        let err = load_template(&root.join("c.md"), ParseOptions::default()).unwrap_err();
+        let msg = err.to_string();
+        assert!(
+            msg.contains("missing.md") && msg.contains("extended from"),
+            "{msg}"
+        );
+        assert!(msg.contains("c.md:2:1"), "{msg}");
+
+        write(&root.join("base.md"), "<!-- block a -->A<!-- endblock -->");
+        write(
+            &root.join("d.md"),
+            "<!-- extends \"base.md\" -->\n<!-- block b -->B<!-- endblock -->",
+        );
+        let err = load_template(&root.join("d.md"), ParseOptions::default()).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("block 'b' is not defined"), "{msg}");
+        assert!(msg.contains("d.md:2:1"), "{msg}");
+    }
+
+    #[test]
+    fn super_without_parent_definition_errors() {
+        let td = setup();
+        let root = td.path();
+        write(
+            &root.join("a.md"),
+            "<!-- block a --><!-- super --><!-- endblock -->",
+        );
+        let tpl = load_template(&root.join("a.md"), ParseOptions::default()).unwrap();
+        let err = tpl.render(root).unwrap_err();
+        assert!(err.to_string().contains("no parent definition"), "{err}");
+    }
+
+    #[test]
+    fn include_expands_tilde() {
+        let td = setup();
+        let home = td.path().join("home");
+        write(&home.join("shared/frag.md"), "F\n");
+        let home_guard = EnvGuard::new("HOME");
+        home_guard.set(&home);
+        write(
+            &td.path().join("main.md"),
+            "<!-- include \"~/shared/frag.md\" -->",
+        );
+        let tpl = load_template(&td.path().join("main.md"), ParseOptions::default()).unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), "F\n");
+    }
+
+    #[test]
+    fn include_cycle_reports_chain() {
+        let td = setup();
+        let root = td.path();
+        write(&root.join("a.md"), "<!-- include \"b.md\" -->");
+        write(&root.join("b.md"), "<!-- include \"a.md\" -->");
+        let err = load_template(&root.join("a.md"), ParseOptions::default()).unwrap_err();
+        match err {
+            Error::Located(d) => {
+                let msg = &d.message;
+                assert!(msg.contains("include cycle"), "{msg}");
+                assert!(msg.contains("a.md -> "), "{msg}");
+                assert!(msg.contains("b.md -> "), "{msg}");
+                assert!(d.to_string().contains("b.md:1:1"), "{d}");
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn missing_include_names_chain() {
+        let td = setup();
+        let root = td.path();
+        write(&root.join("a.md"), "<!-- include \"b.md\" -->");
+        write(&root.join("b.md"), "<!-- include \"nope.md\" -->");
+        let err = load_template(&root.join("a.md"), ParseOptions::default()).unwrap_err();
+        match err {
+            Error::Located(d) => {
+                let msg = &d.message;
+                assert!(msg.contains("nope.md"), "{msg}");
+                assert!(msg.contains("a.md -> "), "{msg}");
+                assert!(msg.contains("b.md"), "{msg}");
+                assert!(d.to_string().contains("b.md:1:1"), "{d}");
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+}
@@ -1,15 +1,31 @@
 use std::{
     env, fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     process,
+    sync::Arc,
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use owo_colors::OwoColorize;
 use similar::TextDiff;
 
+use crate::load::{expand_tilde, paths_equal};
+
+mod cargo;
+mod combine;
+mod context;
+mod deps;
+mod dump;
 mod error;
 mod expr;
+mod facts;
+mod format;
+mod frontmatter;
+mod index;
+mod lint;
+mod load;
+mod markdown;
+mod output;
 mod parse;
 mod project;
 mod template;
@@ -23,10 +39,16 @@ const CLAUDE_MD: &str = "CLAUDE.md";
 #[command(
     name = "agentsmd",
     about = "Render AGENTS.md by combining project and shared templates with simple matchers",
-    version
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_precedence_over_arg = true
 )]
 struct Args {
-    /// Target project path (defaults to CWD)
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Target project path (defaults to CWD). Write a directory named like a
+    /// subcommand as `./fmt` or after `--`
     #[arg(value_name = "path")]
     path: Option<PathBuf>,
 
@@ -57,11 +79,81 @@ struct Args {
     /// Override output file path (relative paths are under project root)
     #[arg(long, value_name = "path")]
     out: Option<PathBuf>,
+
+    /// Allow `{{ env.NAME }}` to interpolate this environment variable (repeatable)
+    #[arg(long = "allow-env", value_name = "name")]
+    allow_env: Vec<String>,
+
+    /// Drop lines that contain only a control tag and whitespace
+    #[arg(long)]
+    trim_tag_lines: bool,
+
+    /// Remove all HTML comments outside code from the rendered output
+    #[arg(long)]
+    strip_comments: bool,
+
+    /// Drop paragraphs and sections repeated from the other template
+    #[arg(long)]
+    dedupe: bool,
+
+    /// How to join the local and shared output
+    #[arg(long, value_enum, default_value_t = combine::Merge::Concat)]
+    merge: combine::Merge,
+
+    /// Threads used to walk the project (0 = one per CPU)
+    #[arg(long, value_name = "n", default_value_t = 0)]
+    threads: usize,
+
+    /// Print the parsed templates as JSON instead of rendering
+    #[arg(long)]
+    dump_ast: bool,
+
+    /// With --dump-ast, annotate each condition with its result for the project
+    #[arg(long, requires = "dump_ast")]
+    eval: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Rewrite templates in canonical form
+    Fmt {
+        /// Report templates that are not formatted and exit non-zero; do not write
+        #[arg(long)]
+        check: bool,
+
+        /// Templates to format (defaults to the project's .agents.md)
+        #[arg(value_name = "path")]
+        paths: Vec<PathBuf>,
+    },
+    /// Report likely mistakes in templates
+    Lint {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = LintFormat::Human)]
+        format: LintFormat,
+
+        /// Templates to lint (defaults to the project's .agents.md)
+        #[arg(value_name = "path")]
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LintFormat {
+    /// One `path:line:col: code: message` line per finding
+    Human,
+    /// A JSON array of findings
+    Json,
 }
 
 fn main() {
     let args = Args::parse();
 
+    match &args.command {
+        Some(Command::Fmt { check, paths }) => process::exit(run_fmt(paths, *check)),
+        Some(Command::Lint { format, paths }) => process::exit(run_lint(paths, *format)),
+        None => {}
+    }
+
     let root = match compute_root(&args) {
         Ok(p) => p,
         Err(e) => {
@@ -72,9 +164,41 @@ fn main() {
     // Resolve optional shared template path: --template > AGENTS_TEMPLATE > ~/.agents.md
     let template_path_opt = resolve_shared_template_path(&args);
 
+    let ctx = context::Context::new(&root)
+        .with_allowed_env(args.allow_env.iter().cloned())
+        .with_threads(args.threads);
+
+    let opts = parse::ParseOptions {
+        trim_tag_lines: args.trim_tag_lines,
+        ..Default::default()
+    };
+
+    if args.dump_ast {
+        match dump_combined(&ctx, template_path_opt.as_deref(), opts, args.eval) {
+            Ok(json) => println!("{json:#}"),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     // Render combined templates; support --stdout and --diff for now.
-    let rendered = match render_combined(&root, template_path_opt.as_deref()) {
-        Ok(s) => s,
+    let shared_path = template_path_opt.as_deref();
+    let rendered = match render_combined(&ctx, shared_path, opts, args.merge, args.dedupe) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    let mut text = rendered.text;
+    if args.strip_comments {
+        text = output::strip_comments(&text);
+    }
+    let targets = match compute_output_paths(&args, &root, &rendered.outputs) {
+        Ok(t) => t,
         Err(e) => {
             eprintln!("{e}");
             process::exit(1);
@@ -82,35 +206,206 @@ fn main() {
     };
 
     if args.diff {
-        let target = compute_output_path(&args, &root);
-        let current = fs::read_to_string(&target).unwrap_or_default();
-        if current == rendered {
-            println!("{}", "No changes".bright_black());
-            return;
+        for target in &targets {
+            let current = fs::read_to_string(target).unwrap_or_default();
+            if current == text {
+                println!("{}", "No changes".bright_black());
+            } else {
+                print_unified_diff(&current, &text, target);
+            }
         }
-        print_unified_diff(&current, &rendered, &target);
         return;
     }
 
     if args.stdout {
-        print!("{rendered}");
+        print!("{text}");
     } else {
-        // Write output (AGENTS.md or CLAUDE.md based on --claude)
-        let out_path = compute_output_path(&args, &root);
-        // Unless --quiet, show diff if there are changes, else "No changes" if nothing to do
-        if !args.quiet {
-            let current = fs::read_to_string(&out_path).unwrap_or_default();
-            if current != rendered {
-                print_unified_diff(&current, &rendered, &out_path);
-            } else {
-                println!("{}", "No changes".bright_black());
+        for out_path in &targets {
+            // Unless --quiet, show diff if there are changes, else "No changes" if nothing to do
+            if !args.quiet {
+                let current = fs::read_to_string(out_path).unwrap_or_default();
+                if current != text {
+                    print_unified_diff(&current, &text, out_path);
+                } else {
+                    println!("{}", "No changes".bright_black());
+                }
+            }
+            if let Err(e) = write_if_changed(out_path, &text) {
+                eprintln!("write error ({}): {e}", out_path.display());
+                process::exit(1);
             }
         }
-        if let Err(e) = write_if_changed(&out_path, &rendered) {
-            eprintln!("write error ({}): {e}", out_path.display());
-            process::exit(1);
+    }
+}
+
+/// The templates named on the command line, or the current project's
+/// `.agents.md` when there are none.
+fn template_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>, error::Error> {
+    if paths.is_empty() {
+        let cwd = env::current_dir().map_err(|e| error::Error::Root(e.to_string()))?;
+        return Ok(vec![project::project_root(cwd)?.join(".agents.md")]);
+    }
+    Ok(paths.iter().map(|p| expand_tilde(p)).collect())
+}
+
+fn read_source(path: &Path) -> Result<Arc<error::Source>, error::Error> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        error::Error::Root(format!("template read error ({}): {e}", path.display()))
+    })?;
+    Ok(Arc::new(error::Source {
+        path: Some(path.to_path_buf()),
+        text,
+    }))
+}
+
+/// Format each template in `paths`, or the project's `.agents.md` when none
+/// are given. With `check`, print a diff for each file that would change
+/// instead of writing it. Returns the process exit code.
+fn run_fmt(paths: &[PathBuf], check: bool) -> i32 {
+    let paths = match template_paths(paths) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+
+    let mut code = 0;
+    for path in &paths {
+        let source = match read_source(path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("{e}");
+                code = 1;
+                continue;
+            }
+        };
+        let formatted = match format::format_source(&source) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{e}");
+                code = 1;
+                continue;
+            }
+        };
+        if formatted == source.text {
+            continue;
+        }
+        if check {
+            print_unified_diff(&source.text, &formatted, path);
+            code = 1;
+        } else if let Err(e) = fs::write(path, &formatted) {
+            eprintln!("write error ({}): {e}", path.display());
+            code = 1;
+        }
+    }
+    code
+}
+
+/// Lint each template in `paths`, or the project's `.agents.md` when none
+/// are given, printing findings and parse errors in `format`. Returns 1 if
+/// anything was reported.
+fn run_lint(paths: &[PathBuf], format: LintFormat) -> i32 {
+    let paths = match template_paths(paths) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    let (report, failed) = lint_report(&paths, format);
+    print!("{report}");
+    i32::from(failed)
+}
+
+/// The findings for the templates at `paths` in `format`, and whether
+/// anything was reported. In the human format, read and parse errors are
+/// printed to stderr rather than included.
+fn lint_report(paths: &[PathBuf], format: LintFormat) -> (String, bool) {
+    let mut report = String::new();
+    let mut findings = Vec::new();
+    let mut failed = false;
+    for path in paths {
+        let source = match read_source(path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("{e}");
+                failed = true;
+                continue;
+            }
+        };
+        let (tpl, errors) = parse::parse_template(&source, parse::ParseOptions::default());
+        if let Err(e) = error::Error::from_all(errors) {
+            failed = true;
+            match format {
+                LintFormat::Human => eprintln!("{e}"),
+                LintFormat::Json => findings.extend(error_findings(&e, &source)),
+            }
+            continue;
+        }
+        for l in lint::lint(&tpl) {
+            failed = true;
+            let (line, col) = source.line_col(l.span.start);
+            match format {
+                LintFormat::Human => report.push_str(&format!(
+                    "{}:{line}:{col}: {}: {}\n",
+                    path.display(),
+                    l.code,
+                    l.message
+                )),
+                LintFormat::Json => {
+                    findings.push(finding(&source, l.span, "warning", l.code, &l.message))
+                }
+            }
         }
     }
+    if format == LintFormat::Json {
+        report = format!("{}\n", serde_json::Value::Array(findings));
+    }
+    (report, failed)
+}
+
+/// One machine-readable lint finding. Lines and columns are 1-based; columns
+/// count characters.
+fn finding(
+    source: &error::Source,
+    span: error::Span,
+    severity: &str,
+    code: &str,
+    message: &str,
+) -> serde_json::Value {
+    let (line, column) = source.line_col(span.start);
+    let (end_line, end_column) = source.line_col(span.end);
+    serde_json::json!({
+        "path": source.path.as_ref().map(|p| p.display().to_string()),
+        "line": line,
+        "column": column,
+        "end_line": end_line,
+        "end_column": end_column,
+        "severity": severity,
+        "code": code,
+        "message": message,
+    })
+}
+
+/// Findings for the parse errors in `err`, which were found in `source`.
+fn error_findings(err: &error::Error, source: &error::Source) -> Vec<serde_json::Value> {
+    match err {
+        error::Error::Multiple(errs) => errs
+            .iter()
+            .flat_map(|e| error_findings(e, source))
+            .collect(),
+        error::Error::Located(d) => {
+            vec![finding(source, d.span, "error", "parse-error", &d.message)]
+        }
+        other => vec![finding(
+            source,
+            error::Span::default(),
+            "error",
+            "parse-error",
+            &other.to_string(),
+        )],
+    }
 }
 
 fn compute_root(args: &Args) -> Result<PathBuf, error::Error> {
@@ -138,16 +433,100 @@ fn resolve_shared_template_path(args: &Args) -> Option<PathBuf> {
     None
 }
 
+/// The combined output and where the templates ask for it to be written.
+#[derive(Debug)]
+struct Rendered {
+    text: String,
+    /// Output files from front matter, relative to the project root; empty
+    /// when no template declares any.
+    outputs: Vec<String>,
+}
+
 fn render_combined(
+    ctx: &context::Context,
+    shared_template_path: Option<&Path>,
+    opts: parse::ParseOptions,
+    merge: combine::Merge,
+    dedupe: bool,
+) -> Result<Rendered, error::Error> {
+    let (local, shared) = load_combined(&ctx.root, shared_template_path, opts)?;
+
+    // Templates are ordered by front-matter priority, highest first; on a tie
+    // the local template leads. Priority only changes the order: local
+    // sections override same-named shared ones either way.
+    let priority = |t: &template::Template| t.front_matter.as_ref().map_or(0, |f| f.priority);
+    let shared_first = matches!((&local, &shared), (Some(l), Some(s)) if priority(s) > priority(l));
+    let mut templates: Vec<template::Template> = local.into_iter().chain(shared).collect();
+    if shared_first {
+        templates.reverse();
+    }
+    let ctx = with_front_matter(ctx, &templates);
+
+    let parts = templates
+        .iter()
+        .map(|t| t.render_parts(&ctx))
+        .collect::<Result<Vec<_>, _>>()?;
+    let text = match parts.as_slice() {
+        [first, second] if shared_first => combine::combine(second, first, merge, true, dedupe),
+        [local, shared] => combine::combine(local, shared, merge, false, dedupe),
+        other => other.iter().map(|p| template::flatten(p)).collect(),
+    };
+    let outputs = templates
+        .iter()
+        .filter_map(|t| t.front_matter.as_ref())
+        .find(|f| !f.outputs.is_empty())
+        .map(|f| f.outputs.clone())
+        .unwrap_or_default();
+    Ok(Rendered { text, outputs })
+}
+
+/// `ctx` with the walk options declared in the front matter of `templates`.
+/// Each option is taken from the first template that sets it.
+fn with_front_matter<'t>(
+    ctx: &context::Context,
+    templates: impl IntoIterator<Item = &'t template::Template>,
+) -> context::Context {
+    let walk = templates
+        .into_iter()
+        .filter_map(|t| t.front_matter.as_ref())
+        .fold(frontmatter::WalkSettings::default(), |acc, f| {
+            acc.or(f.walk)
+        });
+    let mut ctx = ctx.clone();
+    ctx.walk = walk.apply(ctx.walk);
+    ctx
+}
+
+/// The parsed local and shared templates as JSON, in the layout of
+/// `dump::template_json`, optionally annotated with results for `ctx`.
+fn dump_combined(
+    ctx: &context::Context,
+    shared_template_path: Option<&Path>,
+    opts: parse::ParseOptions,
+    eval: bool,
+) -> Result<serde_json::Value, error::Error> {
+    let (local, shared) = load_combined(&ctx.root, shared_template_path, opts)?;
+    let ctx = with_front_matter(ctx, local.iter().chain(&shared));
+    let ctx = eval.then_some(&ctx);
+    Ok(serde_json::json!({
+        "schema": dump::SCHEMA_VERSION,
+        "local": local.map(|t| dump::template_json(&t, ctx)),
+        "shared": shared.map(|t| dump::template_json(&t, ctx)),
+    }))
+}
+
+/// Load the templates that make up the output for the project at `root`:
+/// the local `<root>/.agents.md` and the shared template, each if present.
+/// The shared template is skipped when it is the local one, or when the
+/// local template extends another.
+fn load_combined(
     root: &Path,
     shared_template_path: Option<&Path>,
-) -> Result<String, error::Error> {
+    opts: parse::ParseOptions,
+) -> Result<(Option<template::Template>, Option<template::Template>), error::Error> {
     // Optional project-local template at <root>/.agents.md
     let local_path = root.join(".agents.md");
 
-    // Render local first (if present), then shared. If both paths are the same, render once.
-    let mut out = String::new();
-
     let local_exists = local_path.exists();
     let shared_exists = shared_template_path.map(|p| p.exists()).unwrap_or(false);
 
@@ -157,41 +536,39 @@ fn render_combined(
         ));
     }
 
+    // If both paths are the same, load once.
     let same_path = match shared_template_path {
         Some(p) => paths_equal(&local_path, p),
         None => false,
     };
 
+    // Load both templates before rendering so errors in either are reported
+    // together.
+    let mut errors = Vec::new();
+    let mut local = None;
     if local_exists {
-        let txt = fs::read_to_string(&local_path).map_err(|e| {
-            error::Error::Root(format!(
-                "template read error ({}): {e}",
-                local_path.display()
-            ))
-        })?;
-        let tpl = template::Template::parse(&txt)?;
-        out.push_str(&tpl.render(root)?);
+        match load::load_template(&local_path, opts) {
+            Ok(tpl) => local = Some(tpl),
+            Err(e) => errors.push(e),
+        }
     }
 
+    // A local template that extends another describes the whole document
+    // through its inheritance chain, so the shared template is not appended.
+    let local_extends = local.as_ref().is_some_and(|t| t.extends.is_some());
+    let mut shared = None;
     if let Some(sp) = shared_template_path
         && !same_path
+        && !local_extends
         && sp.exists()
     {
-        let txt = fs::read_to_string(sp).map_err(|e| {
-            error::Error::Root(format!("template read error ({}): {e}", sp.display()))
-        })?;
-        let tpl = template::Template::parse(&txt)?;
-        out.push_str(&tpl.render(root)?);
+        match load::load_template(sp, opts) {
+            Ok(tpl) => shared = Some(tpl),
+            Err(e) => errors.push(e),
+        }
     }
-
-    Ok(out)
-}
-
-fn paths_equal(a: &Path, b: &Path) -> bool {
-    // Compare via absolute components if possible; fall back to direct equality
-    let a_abs = a.canonicalize().unwrap_or_else(|_| a.to_path_buf());
-    let b_abs = b.canonicalize().unwrap_or_else(|_| b.to_path_buf());
-    a_abs == b_abs
+    error::Error::from_all(errors)?;
+    Ok((local, shared))
 }
 
 fn compute_output_path(args: &Args, root: &Path) -> PathBuf {
@@ -207,6 +584,58 @@ fn compute_output_path(args: &Args, root: &Path) -> PathBuf {
     }
 }
 
+/// Where to write the output: the `--out`/`--claude` choice when given,
+/// otherwise the `outputs` declared in front matter, otherwise AGENTS.md.
+/// Front-matter outputs come from the project, so they are never
+/// tilde-expanded and must stay under `root`.
+fn compute_output_paths(
+    args: &Args,
+    root: &Path,
+    outputs: &[String],
+) -> Result<Vec<PathBuf>, error::Error> {
+    if args.out.is_some() || args.claude || outputs.is_empty() {
+        return Ok(vec![compute_output_path(args, root)]);
+    }
+    outputs.iter().map(|o| output_under_root(root, o)).collect()
+}
+
+/// `root` joined with the front-matter output `rel`, which must name a file
+/// under `root`, symbolic links included.
+fn output_under_root(root: &Path, rel: &str) -> Result<PathBuf, error::Error> {
+    let escapes = || {
+        error::Error::Root(format!(
+            "output '{rel}' must be a relative path inside the project root"
+        ))
+    };
+    let mut path = root.to_path_buf();
+    for part in Path::new(rel).components() {
+        match part {
+            Component::Normal(p) if !p.to_string_lossy().starts_with('~') => path.push(p),
+            Component::CurDir => {}
+            Component::ParentDir if path.starts_with(root) && path != root => {
+                path.pop();
+            }
+            _ => return Err(escapes()),
+        }
+    }
+    if path == root {
+        return Err(escapes());
+    }
+    // A symbolic link in the project may still point elsewhere.
+    let real_root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let mut existing = Some(path.as_path());
+    while let Some(dir) = existing {
+        if let Ok(real) = dir.canonicalize() {
+            if !real.starts_with(&real_root) {
+                return Err(escapes());
+            }
+            break;
+        }
+        existing = dir.parent();
+    }
+    Ok(path)
+}
+
 fn write_if_changed(path: &Path, contents: &str) -> Result<(), std::io::Error> {
     match fs::read_to_string(path) {
         Ok(existing) if existing == contents => return Ok(()),
@@ -246,15 +675,16 @@ fn print_unified_diff(current: &str, rendered: &str, target: &Path) {
     }
 }
 
-fn expand_tilde(p: &Path) -> PathBuf {
-    let s = p.to_string_lossy();
-    PathBuf::from(shellexpand::tilde(&s).into_owned())
-}
-
 #[cfg(test)]
 mod tests {
-    use super::{compute_output_path, render_combined, resolve_shared_template_path};
+    use super::{
+        LintFormat, compute_output_path, lint_report, output_under_root, render_combined,
+        resolve_shared_template_path,
+    };
     use crate::Args;
+    use crate::combine::Merge;
+    use crate::context::Context;
+    use crate::parse::ParseOptions;
     use crate::test_support::EnvGuard;
     use crate::{AGENTS_MD, CLAUDE_MD};
     use std::fs;
@@ -279,10 +709,317 @@ mod tests {
         let shared = root.join("shared.md");
         write(&local, "L\n");
         write(&shared, "S\n");
-        let out = render_combined(&root, Some(&shared)).unwrap();
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
         assert_eq!(out, "L\nS\n");
     }
 
+    #[test]
+    fn local_sections_override_shared() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let local = root.join(".agents.md");
+        let shared = root.join("shared.md");
+        write(
+            &local,
+            "L\n<!-- section testing -->Use nextest.\n<!-- endsection -->",
+        );
+        write(
+            &shared,
+            "S\n<!-- section testing -->Use cargo test.\n<!-- endsection -->End\n",
+        );
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
+        assert_eq!(out, "L\nS\nUse nextest.\nEnd\n");
+    }
+
+    #[test]
+    fn heading_merge_interleaves_sections() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let local = root.join(".agents.md");
+        let shared = root.join("shared.md");
+        write(&local, "# Guide\n## Testing\nUse nextest.\n");
+        write(
+            &shared,
+            "# Guide\n## Style\nRun fmt.\n## Testing\nRun all tests.\n",
+        );
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Headings,
+            false,
+        )
+        .unwrap()
+        .text;
+        assert_eq!(
+            out,
+            "# Guide\n## Testing\nUse nextest.\n\nRun all tests.\n## Style\nRun fmt.\n"
+        );
+    }
+
+    #[test]
+    fn subcommand_names_need_a_separator_to_be_paths() {
+        use clap::Parser;
+        let cases: [(&[&str], bool, Option<&str>); 5] = [
+            (&["agentsmd", "fmt"], true, None),
+            (&["agentsmd", "./fmt"], false, Some("./fmt")),
+            (&["agentsmd", "--", "fmt"], false, Some("fmt")),
+            (&["agentsmd", "--stdout", "--", "lint"], false, Some("lint")),
+            (&["agentsmd", "lint", "--", "fmt"], true, None),
+        ];
+        for (argv, subcommand, path) in cases {
+            let args = Args::try_parse_from(argv).unwrap();
+            assert_eq!(args.command.is_some(), subcommand, "{argv:?}");
+            assert_eq!(args.path, path.map(PathBuf::from), "{argv:?}");
+        }
+    }
+
+    #[test]
+    fn lint_reports_in_each_format() {
+        let td = TempDir::new().unwrap();
+        let smelly = td.path().join("smelly.md");
+        let broken = td.path().join("broken.md");
+        let clean = td.path().join("clean.md");
+        write(&smelly, "x\n<!-- if env(A) || !env(A) -->a<!-- endif -->\n");
+        write(&broken, "<!-- endif -->");
+        write(&clean, "<!-- if env(A) -->a<!-- endif -->\n");
+
+        let (out, failed) = lint_report(std::slice::from_ref(&clean), LintFormat::Human);
+        assert_eq!((out.as_str(), failed), ("", false));
+        let (out, failed) = lint_report(std::slice::from_ref(&clean), LintFormat::Json);
+        assert_eq!((out.as_str(), failed), ("[]\n", false));
+
+        let (out, failed) = lint_report(std::slice::from_ref(&smelly), LintFormat::Human);
+        assert!(failed);
+        assert_eq!(
+            out,
+            format!(
+                "{}:2:9: always-true: condition `env(A) || !env(A)` is always true\n",
+                smelly.display()
+            )
+        );
+
+        let (out, failed) = lint_report(&[smelly.clone(), broken.clone()], LintFormat::Json);
+        assert!(failed);
+        let findings: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            findings,
+            serde_json::json!([
+                {
+                    "path": smelly.display().to_string(),
+                    "line": 2,
+                    "column": 9,
+                    "end_line": 2,
+                    "end_column": 26,
+                    "severity": "warning",
+                    "code": "always-true",
+                    "message": "condition `env(A) || !env(A)` is always true",
+                },
+                {
+                    "path": broken.display().to_string(),
+                    "line": 1,
+                    "column": 1,
+                    "end_line": 1,
+                    "end_column": 15,
+                    "severity": "error",
+                    "code": "parse-error",
+                    "message": "stray 'endif'",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn dedupe_drops_only_repeats_of_an_earlier_template() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let shared = root.join("shared.md");
+        write(
+            &root.join(".agents.md"),
+            "# A\n\nRun tests.\n\n# B\n\nRun tests.\n\n",
+        );
+        write(&shared, "# S\n\nRun tests.\n\nShared.\n");
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            true,
+        )
+        .unwrap()
+        .text;
+        assert_eq!(
+            out,
+            "# A\n\nRun tests.\n\n# B\n\nRun tests.\n\n# S\n\nShared.\n"
+        );
+    }
+
+    #[test]
+    fn once_blocks_render_once_across_templates() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let local = root.join(".agents.md");
+        let shared = root.join("shared.md");
+        write(
+            &local,
+            "L\n<!-- once \"fmt\" -->Run cargo fmt.\n<!-- endonce -->",
+        );
+        write(
+            &shared,
+            "S\n<!-- once fmt -->Run cargo fmt.\n<!-- endonce --><!-- once lint -->Run clippy.\n<!-- endonce -->",
+        );
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
+        assert_eq!(out, "L\nRun cargo fmt.\nS\nRun clippy.\n");
+    }
+
+    #[test]
+    fn front_matter_outputs_stay_under_the_root() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().join("project");
+        let outside = td.path().join("home");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        assert_eq!(
+            output_under_root(&root, "docs/./AGENTS.md").unwrap(),
+            root.join("docs/AGENTS.md")
+        );
+        assert_eq!(
+            output_under_root(&root, "docs/../AGENTS.md").unwrap(),
+            root.join("AGENTS.md")
+        );
+        let mut escapes = vec![
+            "~/pwned.md",
+            "a/../../pwned.md",
+            "/tmp/pwned.md",
+            ".",
+            "a/..",
+        ];
+        if cfg!(unix) {
+            escapes.push("link/pwned.md");
+        }
+        for rel in escapes {
+            let err = output_under_root(&root, rel).unwrap_err().to_string();
+            assert!(err.contains("inside the project root"), "{rel}: {err}");
+        }
+        assert!(!outside.join("pwned.md").exists());
+    }
+
+    #[test]
+    fn front_matter_orders_templates_and_sets_outputs() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        write(&root.join(".hidden/x.rs"), "");
+        let local = root.join(".agents.md");
+        let shared = root.join("shared.md");
+        write(
+            &local,
+            "---\noutputs: [AGENTS.md, docs/AGENTS.md]\n---\nL\n\
+             <!-- section testing -->Use nextest.\n<!-- endsection -->",
+        );
+        write(
+            &shared,
+            "+++\npriority = 1\n[walk]\nhidden = false\n+++\n\
+             S <!-- if lang(rust) -->rust<!-- endif -->\n\
+             <!-- section testing -->Use cargo test.\n<!-- endsection -->",
+        );
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap();
+        // The shared template outranks the local one, so it comes first, but
+        // the local section still replaces the shared one; the shared front
+        // matter also hides `.hidden/x.rs` from lang().
+        assert_eq!(out.text, "S \nUse nextest.\nL\n");
+        assert_eq!(out.outputs, vec!["AGENTS.md", "docs/AGENTS.md"]);
+    }
+
+    #[test]
+    fn reports_errors_from_local_and_shared_together() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let shared = root.join("shared.md");
+        write(
+            &shared,
+            "ok\n<!-- endfor -->\n<!-- if nope -->x<!-- endif -->",
+        );
+        write(&root.join(".agents.md"), "<!-- endif -->");
+        let err = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap_err()
+        .to_string();
+        let local = err.find(".agents.md:1:1").expect(&err);
+        let endfor = err.find("shared.md:2:1").expect(&err);
+        let bad_if = err.find("shared.md:3:9").expect(&err);
+        assert!(local < endfor && endfor < bad_if, "{err}");
+        assert!(err.ends_with("3 template errors"), "{err}");
+    }
+
+    #[test]
+    fn local_extends_replaces_concatenation() {
+        let td = TempDir::new().unwrap();
+        let root = td.path().to_path_buf();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let shared = root.join("shared.md");
+        write(&shared, "# Shared\n<!-- block extra --><!-- endblock -->");
+        write(
+            &root.join(".agents.md"),
+            "<!-- extends \"shared.md\" --><!-- block extra -->Local\n<!-- endblock -->",
+        );
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
+        assert_eq!(out, "# Shared\nLocal\n");
+    }
+
     #[test]
     fn local_is_full_template() {
         let td = TempDir::new().unwrap();
@@ -299,7 +1036,15 @@ mod tests {
         // Shared template empty
         let shared = root.join("shared.md");
         write(&shared, "");
-        let out = render_combined(&root, Some(&shared)).unwrap();
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
         assert!(out.contains("Before\n"));
         assert!(out.contains("Hit\n"));
         assert!(out.contains("After\n"));
@@ -313,7 +1058,15 @@ mod tests {
         let local = root.join(".agents.md");
         write(&local, "OnlyOnce\n");
         // Use the same path for shared
-        let out = render_combined(&root, Some(&local)).unwrap();
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&local),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
         assert_eq!(out, "OnlyOnce\n");
     }
 
@@ -323,7 +1076,14 @@ mod tests {
         let root = td.path().to_path_buf();
         fs::create_dir_all(root.join(".git")).unwrap();
         let shared = root.join("nope.md");
-        let err = render_combined(&root, Some(&shared)).unwrap_err();
+        let err = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap_err();
         match err {
             crate::error::Error::Root(msg) => assert!(msg.contains("no template found")),
             other => panic!("unexpected error: {other:?}"),
@@ -339,7 +1099,15 @@ mod tests {
         write(&local, "LocalOnly\n");
         // Shared path missing
         let shared = root.join("nope.md");
-        let out = render_combined(&root, Some(&shared)).unwrap();
+        let out = render_combined(
+            &Context::new(&root),
+            Some(&shared),
+            ParseOptions::default(),
+            Merge::Concat,
+            false,
+        )
+        .unwrap()
+        .text;
         assert_eq!(out, "LocalOnly\n");
     }
 
@@ -354,6 +1122,7 @@ mod tests {
 
         // ~ in --template
         let args = Args {
+            command: None,
             path: None,
             template: Some(PathBuf::from("~/shared.md")),
             root: None,
@@ -362,6 +1131,14 @@ mod tests {
             quiet: false,
             claude: false,
             out: None,
+            allow_env: Vec::new(),
+            trim_tag_lines: false,
+            strip_comments: false,
+            dedupe: false,
+            merge: Merge::Concat,
+            threads: 0,
+            dump_ast: false,
+            eval: false,
         };
         let p = resolve_shared_template_path(&args).unwrap();
         assert_eq!(p, home.join("shared.md"));
@@ -0,0 +1,236 @@
+use std::ops::Range;
+
+/// The first occurrence of `needle` at or after `from` that is not inside a
+/// fenced code block or an inline code span. `from` must itself be outside
+/// code.
+///
+/// Fences are runs of at least three backticks or tildes that start a line,
+/// optionally indented and followed by an info string; they end at a line
+/// holding a run of the same character at least as long, or at the end of
+/// the text. A code span is a run of backticks closed by a run of the same
+/// length within the same paragraph; an unclosed run is literal text.
+pub fn find_outside_code(text: &str, from: usize, needle: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut i = from;
+    while i < text.len() {
+        if text[i..].starts_with(needle) {
+            return Some(i);
+        }
+        match bytes[i] {
+            c @ (b'`' | b'~') => {
+                let run = run_len(bytes, i, c);
+                if starts_line(text, i)
+                    && let Some(end) = fence_end(text, i, c, run)
+                {
+                    i = end;
+                } else if c == b'`' {
+                    i = span_end(text, i, run).unwrap_or(i + run);
+                } else {
+                    i += run;
+                }
+            }
+            _ => i += text[i..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    None
+}
+
+/// A top‑level piece of a Markdown document: an ATX heading line, or a run of
+/// non‑blank lines. Fenced code is never split and never holds headings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Byte range of the chunk's lines, including the last line's newline.
+    pub range: Range<usize>,
+    /// The heading level, for a heading.
+    pub heading: Option<usize>,
+}
+
+/// Split `text` into chunks. Blank lines between chunks belong to none.
+pub fn chunks(text: &str) -> Vec<Chunk> {
+    let mut out = Vec::new();
+    let mut cur: Option<Range<usize>> = None;
+    let mut pos = 0;
+    while pos < text.len() {
+        let end = text[pos..].find('\n').map_or(text.len(), |n| pos + n + 1);
+        let line = &text[pos..end];
+        let body = line.trim_start_matches([' ', '\t']);
+        let indent = line.len() - body.len();
+        if body.trim().is_empty() {
+            out.extend(cur.take().map(|range| Chunk {
+                range,
+                heading: None,
+            }));
+            pos = end;
+        } else if let Some(level) = heading_level(line) {
+            out.extend(cur.take().map(|range| Chunk {
+                range,
+                heading: None,
+            }));
+            out.push(Chunk {
+                range: pos..end,
+                heading: Some(level),
+            });
+            pos = end;
+        } else {
+            let c = body.as_bytes()[0];
+            let fence = matches!(c, b'`' | b'~')
+                .then(|| fence_end(text, pos + indent, c, run_len(body.as_bytes(), 0, c)))
+                .flatten();
+            let next = fence.unwrap_or(end);
+            cur = Some(cur.map_or(pos, |r| r.start)..next);
+            pos = next;
+        }
+    }
+    out.extend(cur.map(|range| Chunk {
+        range,
+        heading: None,
+    }));
+    out
+}
+
+/// The level of an ATX heading line: up to three spaces of indentation, one
+/// to six `#`, then a space or the end of the line.
+pub fn heading_level(line: &str) -> Option<usize> {
+    let body = line.trim_end_matches(['\n', '\r']);
+    let rest = body.trim_start_matches(' ');
+    if body.len() - rest.len() > 3 {
+        return None;
+    }
+    let level = rest.bytes().take_while(|b| *b == b'#').count();
+    let after = &rest[level..];
+    ((1..=6).contains(&level) && (after.is_empty() || after.starts_with([' ', '\t'])))
+        .then_some(level)
+}
+
+/// Length of the run of byte `c` starting at `i`.
+fn run_len(bytes: &[u8], i: usize, c: u8) -> usize {
+    bytes[i..].iter().take_while(|b| **b == c).count()
+}
+
+/// Whether only spaces and tabs precede byte `i` on its line.
+fn starts_line(text: &str, i: usize) -> bool {
+    let before = text[..i].trim_end_matches([' ', '\t']);
+    before.is_empty() || before.ends_with('\n')
+}
+
+/// If a run of `run` `c` characters at `i` opens a fence, the offset just
+/// past its closing line.
+fn fence_end(text: &str, i: usize, c: u8, run: usize) -> Option<usize> {
+    if run < 3 {
+        return None;
+    }
+    let line_end = text[i..].find('\n').map_or(text.len(), |n| i + n + 1);
+    if c == b'`' && text[i + run..line_end].contains('`') {
+        // Info strings of backtick fences cannot hold backticks, so this is
+        // inline code.
+        return None;
+    }
+    let mut pos = line_end;
+    for line in text[line_end..].split_inclusive('\n') {
+        pos += line.len();
+        let body = line.trim_start_matches([' ', '\t']);
+        let close = run_len(body.as_bytes(), 0, c);
+        if close >= run && body[close..].trim().is_empty() {
+            return Some(pos);
+        }
+    }
+    Some(text.len())
+}
+
+/// If a run of `run` backticks at `i` opens a code span, the offset just past
+/// its closing run.
+fn span_end(text: &str, i: usize, run: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut j = i + run;
+    while j < text.len() {
+        match bytes[j] {
+            b'`' => {
+                let close = run_len(bytes, j, b'`');
+                if close == run {
+                    return Some(j + close);
+                }
+                j += close;
+            }
+            b'\n'
+                if text[j + 1..]
+                    .split('\n')
+                    .next()
+                    .is_some_and(|l| l.trim().is_empty()) =>
+            {
+                return None;
+            }
+            _ => j += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_code() {
+        let cases = [
+            ("plain", "a <!-- x -->", Some("<!-- x -->")),
+            ("inline code", "`<!-- a -->` <!-- b -->", Some("<!-- b -->")),
+            (
+                "double backticks",
+                "`` ` <!-- a --> `` <!-- b -->",
+                Some("<!-- b -->"),
+            ),
+            ("unclosed backtick", "it`s <!-- b -->", Some("<!-- b -->")),
+            (
+                "span ends at a blank line",
+                "`a\n\n<!-- b --> `",
+                Some("<!-- b --> `"),
+            ),
+            (
+                "backtick fence",
+                "```html\n<!-- a -->\n```\n<!-- b -->",
+                Some("<!-- b -->"),
+            ),
+            (
+                "tilde fence with longer close",
+                "  ~~~\n<!-- a -->\n~~~~~\n<!-- b -->",
+                Some("<!-- b -->"),
+            ),
+            (
+                "shorter run does not close",
+                "````\n```\n<!-- a -->\n````\n<!-- b -->",
+                Some("<!-- b -->"),
+            ),
+            ("unclosed fence runs to the end", "```\n<!-- a -->\n", None),
+            (
+                "fence must start its line",
+                "x ~~~\n<!-- b -->",
+                Some("<!-- b -->"),
+            ),
+        ];
+        for (name, text, want) in cases {
+            let got = find_outside_code(text, 0, "<!--").map(|i| &text[i..]);
+            assert_eq!(got, want, "case: {name}");
+        }
+    }
+
+    #[test]
+    fn splits_into_chunks() {
+        let text = "# Title\nintro\nmore\n\n\n## Build\n```sh\n# not a heading\n\nmake\n```\nafter\n####### deep\n#tag";
+        let got: Vec<(&str, Option<usize>)> = chunks(text)
+            .into_iter()
+            .map(|c| (&text[c.range], c.heading))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("# Title\n", Some(1)),
+                ("intro\nmore\n", None),
+                ("## Build\n", Some(2)),
+                (
+                    "```sh\n# not a heading\n\nmake\n```\nafter\n####### deep\n#tag",
+                    None
+                ),
+            ]
+        );
+    }
+}
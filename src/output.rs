@@ -0,0 +1,200 @@
+use crate::markdown::{chunks, find_outside_code};
+use std::collections::HashSet;
+
+/// Remove every `<!-- … -->` comment outside code from rendered output.
+///
+/// A line left holding only whitespace once its comments are removed is
+/// dropped along with its newline, so stripping does not leave blank lines
+/// behind. An unterminated `<!--` is kept as text.
+pub fn strip_comments(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut pos = 0;
+    while let Some(start) = find_outside_code(s, pos, "<!--") {
+        let Some(len) = s[start + 4..].find("-->") else {
+            break;
+        };
+        out.push_str(&s[pos..start]);
+        let rest = &s[start + 4 + len + 3..];
+        pos = s.len() - rest.len();
+
+        let line_start = out.rfind('\n').map_or(0, |i| i + 1);
+        let before_blank = out[line_start..].chars().all(|c| c == ' ' || c == '\t');
+        let after = rest.trim_start_matches([' ', '\t']);
+        let nl = if after.starts_with("\r\n") {
+            Some(2)
+        } else if after.starts_with('\n') || after.is_empty() {
+            Some(usize::from(!after.is_empty()))
+        } else {
+            None
+        };
+        if before_blank && let Some(nl) = nl {
+            out.truncate(line_start);
+            pos = s.len() - after.len() + nl;
+        }
+    }
+    out.push_str(&s[pos..]);
+    out
+}
+
+/// Drop Markdown sections and paragraphs of each document in `docs` that
+/// repeat ones of an earlier document, keeping the first copy. Repeats within
+/// one document are kept.
+///
+/// A section is a heading with everything up to the next heading of the same
+/// or a higher level; it is dropped when its heading and body both repeat a
+/// section of an earlier document. Then any paragraph with text that repeats
+/// a paragraph kept in an earlier document is dropped. Headings alone are
+/// never dropped, and comparisons ignore trailing whitespace. Blank lines
+/// after a dropped piece go with it.
+pub fn dedupe(docs: &[&str]) -> Vec<String> {
+    let mut sections = HashSet::new();
+    let mut paragraphs = HashSet::new();
+    docs.iter()
+        .map(|s| dedupe_against(s, &mut sections, &mut paragraphs))
+        .collect()
+}
+
+/// `s` less the sections and paragraphs in `sections` and `paragraphs`, to
+/// which those of `s` are then added.
+fn dedupe_against(
+    s: &str,
+    sections: &mut HashSet<String>,
+    paragraphs: &mut HashSet<String>,
+) -> String {
+    let chunks = chunks(s);
+    let key = |start: usize, end: usize| -> String {
+        s[start..end]
+            .trim()
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let mut drop = vec![false; chunks.len()];
+    let mut own_sections = Vec::new();
+    for (i, c) in chunks.iter().enumerate() {
+        let Some(level) = c.heading else { continue };
+        if drop[i] {
+            continue;
+        }
+        let end = chunks[i + 1..]
+            .iter()
+            .position(|n| n.heading.is_some_and(|l| l <= level))
+            .map_or(chunks.len(), |n| i + 1 + n);
+        let k = key(c.range.start, chunks[end - 1].range.end);
+        if sections.contains(&k) {
+            drop[i..end].fill(true);
+        } else {
+            own_sections.push(k);
+        }
+    }
+    let mut own_paragraphs = Vec::new();
+    for (i, c) in chunks.iter().enumerate() {
+        let k = key(c.range.start, c.range.end);
+        if drop[i] || c.heading.is_some() || !k.chars().any(char::is_alphanumeric) {
+            continue;
+        }
+        if paragraphs.contains(&k) {
+            drop[i] = true;
+        } else {
+            own_paragraphs.push(k);
+        }
+    }
+    sections.extend(own_sections);
+    paragraphs.extend(own_paragraphs);
+
+    let mut out = String::with_capacity(s.len());
+    out.push_str(&s[..chunks.first().map_or(s.len(), |c| c.range.start)]);
+    for (i, c) in chunks.iter().enumerate() {
+        if !drop[i] {
+            let next = chunks.get(i + 1).map_or(s.len(), |n| n.range.start);
+            out.push_str(&s[c.range.start..next]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_comments_and_comment_only_lines() {
+        let cases = [
+            ("no comments", "a\nb\n", "a\nb\n"),
+            ("inline", "a <!-- x --> b\n", "a  b\n"),
+            ("whole line", "a\n  <!-- x -->  \nb\n", "a\nb\n"),
+            (
+                "several on one line",
+                "a\n<!-- x --><!-- y -->\nb\n",
+                "a\nb\n",
+            ),
+            ("multi-line comment", "a\n<!-- x\ny -->\nb\n", "a\nb\n"),
+            ("at end of input", "a\n<!-- x -->", "a\n"),
+            ("crlf", "a\r\n<!-- x -->\r\nb\r\n", "a\r\nb\r\n"),
+            ("unterminated", "a <!-- x\n", "a <!-- x\n"),
+            (
+                "code is kept",
+                "```html\n<!-- x -->\n```\n`<!-- y -->`<!-- z -->\n",
+                "```html\n<!-- x -->\n```\n`<!-- y -->`\n",
+            ),
+        ];
+        for (name, input, expect) in cases {
+            assert_eq!(strip_comments(input), expect, "case: {name}");
+        }
+    }
+
+    #[test]
+    fn dedupe_keeps_first_copies() {
+        let cases: [(&str, &[&str], &[&str]); 7] = [
+            (
+                "nothing repeated",
+                &["# A\n\none\n", "two\n"],
+                &["# A\n\none\n", "two\n"],
+            ),
+            (
+                "repeated paragraph",
+                &[
+                    "# L\n\nRun tests.\n\nLocal.\n\n",
+                    "# S\n\nRun tests.  \n\nShared.\n",
+                ],
+                &["# L\n\nRun tests.\n\nLocal.\n\n", "# S\n\nShared.\n"],
+            ),
+            (
+                "repeats within one template stay",
+                &["# A\n\nRun tests.\n\n# B\n\nRun tests.\n"],
+                &["# A\n\nRun tests.\n\n# B\n\nRun tests.\n"],
+            ),
+            (
+                "repeated section",
+                &[
+                    "## Testing\n\nRun tests.\n### Notes\nok\n\n## Style\nfmt\n\n",
+                    "## Testing\n\nRun tests.\n### Notes\nok\n\n## End\nbye\n",
+                ],
+                &[
+                    "## Testing\n\nRun tests.\n### Notes\nok\n\n## Style\nfmt\n\n",
+                    "## End\nbye\n",
+                ],
+            ),
+            (
+                "same heading with a new body keeps the heading",
+                &["## Testing\nA\n\n", "## Testing\nA\n\nB\n"],
+                &["## Testing\nA\n\n", "## Testing\nB\n"],
+            ),
+            (
+                "rules and blank text stay",
+                &["a\n\n---\n\n", "b\n\n---\n"],
+                &["a\n\n---\n\n", "b\n\n---\n"],
+            ),
+            (
+                "code blocks compare whole",
+                &["```\nx\n\ny\n```\n\n", "y\n\n```\nx\n\ny\n```\n"],
+                &["```\nx\n\ny\n```\n\n", "y\n\n"],
+            ),
+        ];
+        for (name, input, expect) in cases {
+            assert_eq!(dedupe(input), expect, "case: {name}");
+        }
+    }
+}
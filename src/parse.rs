@@ -1,85 +1,884 @@
-use crate::error::Error;
-use crate::expr::{Expr, Matcher};
-use crate::template::{Block, Template};
+use crate::context::VarKind;
+use crate::deps::Ecosystem;
+use crate::error::{Error, Source, Span};
+use crate::expr::{Definition, Expr, Matcher, compile_glob};
+use crate::facts::{Fact, Piece, split_interpolations};
+use crate::frontmatter::{FrontMatter, split_front_matter};
+use crate::markdown::find_outside_code;
+use crate::template::{Block, Branch, Extends, LoopSource, SectionMode, Template, Trim};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-pub fn parse_template(input: &str) -> Result<Template, Error> {
-    TemplateParser::new(input).parse()
+/// Parse `source`, returning a best‑effort template together with every
+/// error found; the template is only meaningful when there are none.
+pub fn parse_template(source: &Arc<Source>, opts: ParseOptions) -> (Template, Vec<Error>) {
+    TemplateParser::new(source, opts).parse()
+}
+
+/// Options controlling how template source is turned into blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Remove lines that hold nothing but a control tag, including the
+    /// line's indentation and trailing newline.
+    pub trim_tag_lines: bool,
+    /// Keep text exactly as written: trim markers are recorded on the blocks
+    /// but not applied. Used when the template is printed back out.
+    pub lossless: bool,
+}
+
+/// Tag keywords recognised after `<!--`; any other comment is literal text.
+const CONTROL_KEYWORDS: &[&str] = &[
+    "if",
+    "elif",
+    "else",
+    "endif",
+    "include",
+    "section",
+    "endsection",
+    "extends",
+    "block",
+    "endblock",
+    "super",
+    "for",
+    "endfor",
+    "define",
+    "raw",
+    "endraw",
+    "once",
+    "endonce",
+];
+
+/// Names that start a matcher and so cannot be `define`d.
+const MATCHER_NAMES: &[&str] = &[
+    "exists",
+    "lang",
+    "env",
+    "contains",
+    "dep",
+    "cargo_workspace",
+    "cargo_edition",
+    "cargo_feature",
+];
+
+/// Source of [`Definition::id`]s.
+static NEXT_DEFINITION: AtomicUsize = AtomicUsize::new(0);
+
+/// Conditions `define`d so far in a template, by name.
+type Definitions = HashMap<String, Arc<Definition>>;
+
+/// A control block that has been opened but not yet closed.
+enum Frame {
+    If {
+        /// Blocks preceding the `if` tag at the enclosing level.
+        parent: Vec<Block>,
+        /// Completed `if`/`elif` arms.
+        branches: Vec<Branch>,
+        /// Condition of the arm being collected; `None` once inside `else`.
+        cond: Option<Expr>,
+        /// Trim markers of the tag that opened the arm being collected.
+        trim: Trim,
+        span: Span,
+    },
+    Section {
+        parent: Vec<Block>,
+        name: String,
+        mode: SectionMode,
+        open: Trim,
+        span: Span,
+    },
+    Slot {
+        parent: Vec<Block>,
+        name: String,
+        open: Trim,
+        span: Span,
+    },
+    Once {
+        parent: Vec<Block>,
+        key: String,
+        open: Trim,
+        span: Span,
+    },
+    For {
+        parent: Vec<Block>,
+        var: String,
+        source: LoopSource,
+        open: Trim,
+        /// The opening tag, reported when the loop source fails to evaluate.
+        span: Span,
+    },
+    /// An opening tag whose argument was rejected. It stays on the stack so
+    /// its closing tag is not reported as stray. A rejected `for` still binds
+    /// its variable so the body does not report it as an unknown fact.
+    Invalid {
+        kw: &'static str,
+        span: Span,
+        var: Option<String>,
+    },
+}
+
+/// Loop variables bound by the open `for` frames, innermost last.
+fn bound_vars(stack: &[Frame]) -> Vec<(&str, VarKind)> {
+    stack
+        .iter()
+        .filter_map(|f| match f {
+            Frame::For { var, source, .. } => Some((var.as_str(), source.kind())),
+            Frame::Invalid { var: Some(var), .. } => Some((var.as_str(), VarKind::File)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Check that every `{{ … }}` in `s` names a known fact or bound variable.
+///
+/// `s` is an unescaped argument, so offsets within it do not map back to the
+/// template; errors are left for the caller to locate.
+fn check_refs(s: &str, vars: &[(&str, VarKind)]) -> Result<(), Error> {
+    let pieces = split_interpolations(s).map_err(|e| match e {
+        Error::Located(d) => Error::Template(d.message),
+        e => e,
+    })?;
+    for piece in pieces {
+        if let Piece::Ref(name) = piece {
+            Fact::parse_in(name, vars)?;
+        }
+    }
+    Ok(())
+}
+
+impl Frame {
+    /// The error message for a block left open at the end of the template,
+    /// and the span of its opening tag.
+    fn unclosed(&self) -> (&'static str, Span) {
+        let (kw, span) = match self {
+            Frame::If { span, .. } => ("if", span),
+            Frame::Once { span, .. } => ("once", span),
+            Frame::Section { span, .. } => ("section", span),
+            Frame::Slot { span, .. } => ("block", span),
+            Frame::For { span, .. } => ("for", span),
+            Frame::Invalid { kw, span, .. } => (*kw, span),
+        };
+        let msg = match kw {
+            "if" => "unclosed 'if' block",
+            "section" => "unclosed 'section' block",
+            "block" => "unclosed 'block' block",
+            "once" => "unclosed 'once' block",
+            _ => "unclosed 'for' block",
+        };
+        (msg, *span)
+    }
+
+    /// Whether this is the rejected opener of a `kw` block.
+    fn is_invalid(&self, kw: &str) -> bool {
+        matches!(self, Frame::Invalid { kw: k, .. } if *k == kw)
+    }
+
+    /// Blocks preceding this frame's opening tag, if it collected any.
+    fn into_parent(self) -> Option<Vec<Block>> {
+        match self {
+            Frame::If { parent, .. }
+            | Frame::Section { parent, .. }
+            | Frame::Slot { parent, .. }
+            | Frame::Once { parent, .. }
+            | Frame::For { parent, .. } => Some(parent),
+            Frame::Invalid { .. } => None,
+        }
+    }
 }
 
 struct TemplateParser<'a> {
+    source: &'a Arc<Source>,
     src: &'a str,
     idx: usize,
+    opts: ParseOptions,
+    /// Open control blocks, innermost last.
+    stack: Vec<Frame>,
+    /// Blocks collected at the current nesting level.
+    cur: Vec<Block>,
+    extends: Option<Extends>,
+    front_matter: Option<FrontMatter>,
+    slot_names: HashSet<String>,
+    definitions: Definitions,
+    /// Errors from tags and text that were skipped so parsing could continue.
+    errors: Vec<Error>,
 }
 
 impl<'a> TemplateParser<'a> {
-    fn new(src: &'a str) -> Self {
-        Self { src, idx: 0 }
+    fn new(source: &'a Arc<Source>, opts: ParseOptions) -> Self {
+        Self {
+            source,
+            src: &source.text,
+            idx: 0,
+            opts,
+            stack: Vec::new(),
+            cur: Vec::new(),
+            extends: None,
+            front_matter: None,
+            slot_names: HashSet::new(),
+            definitions: HashMap::new(),
+            errors: Vec::new(),
+        }
     }
 
-    fn parse(mut self) -> Result<Template, Error> {
-        let mut stack: Vec<(Expr, Vec<Block>)> = Vec::new();
-        let mut cur: Vec<Block> = Vec::new();
-
+    /// Parse the whole template. After an error the parser resumes at the end
+    /// of the offending tag or text, so every error is reported; the template
+    /// is returned along with them, with unclosed blocks flattened into their
+    /// parents.
+    fn parse(mut self) -> (Template, Vec<Error>) {
+        if let Some((body, res)) = split_front_matter(self.src) {
+            self.idx = body;
+            match res {
+                Ok(fm) => self.front_matter = Some(fm),
+                Err(e) => self.errors.push(e.in_source(self.source)),
+            }
+        }
         while self.idx < self.src.len() {
-            if let Some(tag_start) = self.find("<!--") {
-                if tag_start > self.idx {
-                    let txt = &self.src[self.idx..tag_start];
-                    if !txt.is_empty() {
-                        cur.push(Block::Text(txt.to_string()))
+            if let Err(e) = self.step() {
+                // An unterminated tag runs to the end of the template.
+                self.errors.push(e);
+                break;
+            }
+        }
+
+        for frame in std::mem::take(&mut self.stack).into_iter().rev() {
+            let (msg, span) = frame.unclosed();
+            let err = self.locate(Error::Template(msg.into()), span);
+            self.errors.push(err);
+            if let Some(mut parent) = frame.into_parent() {
+                parent.append(&mut self.cur);
+                self.cur = parent;
+            }
+        }
+
+        let tpl = Template {
+            blocks: self.cur,
+            extends: self.extends,
+            front_matter: self.front_matter,
+            source: self.source.clone(),
+        };
+        (tpl, self.errors)
+    }
+
+    /// Consume the text up to the next tag and the tag itself. Errors in
+    /// either are recorded; only an unterminated tag is returned.
+    fn step(&mut self) -> Result<(), Error> {
+        let text_start = self.idx;
+        let Some(tag_start) = find_outside_code(self.src, self.idx, "<!--") else {
+            self.idx = self.src.len();
+            self.text(text_start, self.src.len());
+            return Ok(());
+        };
+        if self.src[..tag_start].ends_with('\\') {
+            // `\<!--` is a literal `<!--` rather than the start of a tag.
+            if tag_start - 1 > text_start {
+                self.text(text_start, tag_start - 1);
+            }
+            self.cur.push(Block::Text("<!--".into()));
+            self.idx = tag_start + 4;
+            return Ok(());
+        }
+        if tag_start > self.idx {
+            self.text(text_start, tag_start);
+        }
+        self.idx = tag_start + 4; // after <!--
+        let left = self.consume("-");
+        self.skip_ws();
+        if self.consume("#") {
+            // private comment: kept in the AST, never rendered
+            let (_, text, right) = self.read_tag_body(tag_start)?;
+            let trim = Trim { left, right };
+            self.apply_trim(tag_start, trim);
+            self.cur.push(Block::Comment {
+                text: text.trim().to_string(),
+                trim,
+            });
+            return Ok(());
+        }
+        let Some(kw) = self.control_keyword() else {
+            return self.literal_comment(tag_start);
+        };
+        let (arg_start, arg, right) = self.read_tag_body(tag_start)?;
+        if !is_tag(self.src, kw, arg, arg_start) {
+            // prose that happens to start with a keyword, like
+            // `<!-- for details see below -->`
+            return self.literal_comment(tag_start);
+        }
+        let trim = Trim { left, right };
+        let tag = Span::new(tag_start, self.idx);
+        self.apply_trim(tag_start, trim);
+        if let Err(e) = self.control_tag(kw, arg_start, arg, trim, tag) {
+            let err = self.locate(e, tag);
+            self.errors.push(err);
+        }
+        Ok(())
+    }
+
+    /// Push the comment opened at `tag_start` as literal text.
+    fn literal_comment(&mut self, tag_start: usize) -> Result<(), Error> {
+        self.idx = tag_start + 4;
+        let inner = self
+            .read_until("-->")
+            .map_err(|e| self.locate(e, Span::new(tag_start, self.src.len())))?;
+        self.idx += 3; // -->
+        let mut s = String::from("<!--");
+        s.push_str(inner);
+        s.push_str("-->");
+        self.cur.push(Block::Text(s));
+        Ok(())
+    }
+
+    /// Handle control tag `kw` with argument text `arg`, which starts at byte
+    /// `arg_start`; `tag` spans the whole tag.
+    fn control_tag(
+        &mut self,
+        kw: &str,
+        arg_start: usize,
+        arg: &str,
+        trim: Trim,
+        tag: Span,
+    ) -> Result<(), Error> {
+        match kw {
+            "if" => {
+                let vars = bound_vars(&self.stack);
+                let res = parse_tag_expr(arg, arg_start, &vars, &self.definitions, None);
+                let expr = self.opener("if", tag, res)?;
+                self.stack.push(Frame::If {
+                    parent: std::mem::take(&mut self.cur),
+                    branches: Vec::new(),
+                    cond: Some(expr),
+                    trim,
+                    span: tag,
+                });
+            }
+            "elif" => {
+                let vars = bound_vars(&self.stack);
+                let expr = parse_tag_expr(arg, arg_start, &vars, &self.definitions, None)?;
+                match self.stack.last_mut() {
+                    Some(f) if f.is_invalid("if") => {}
+                    Some(Frame::If {
+                        branches,
+                        cond,
+                        trim: arm_trim,
+                        ..
+                    }) => {
+                        let prev = cond
+                            .take()
+                            .ok_or_else(|| Error::Template("'elif' after 'else'".into()))?;
+                        branches.push(Branch {
+                            cond: prev,
+                            body: std::mem::take(&mut self.cur),
+                            trim: *arm_trim,
+                        });
+                        *cond = Some(expr);
+                        *arm_trim = trim;
+                    }
+                    _ => return Err(Error::Template("stray 'elif'".into())),
+                }
+            }
+            "else" => {
+                expect_no_arg("else", arg)?;
+                match self.stack.last_mut() {
+                    Some(f) if f.is_invalid("if") => {}
+                    Some(Frame::If {
+                        branches,
+                        cond,
+                        trim: arm_trim,
+                        ..
+                    }) => {
+                        let prev = cond
+                            .take()
+                            .ok_or_else(|| Error::Template("duplicate 'else'".into()))?;
+                        branches.push(Branch {
+                            cond: prev,
+                            body: std::mem::take(&mut self.cur),
+                            trim: *arm_trim,
+                        });
+                        *arm_trim = trim;
+                    }
+                    _ => return Err(Error::Template("stray 'else'".into())),
+                }
+            }
+            "endif" => {
+                expect_no_arg("endif", arg)?;
+                let (parent, mut branches, cond, arm_trim) = match self.stack.pop() {
+                    Some(Frame::If {
+                        parent,
+                        branches,
+                        cond,
+                        trim,
+                        ..
+                    }) => (parent, branches, cond, trim),
+                    Some(f) if f.is_invalid("if") => return Ok(()),
+                    Some(other) => {
+                        self.stack.push(other);
+                        return Err(Error::Template("stray 'endif'".into()));
+                    }
+                    None => return Err(Error::Template("stray 'endif'".into())),
+                };
+                let body = std::mem::take(&mut self.cur);
+                // A pending condition means the last arm was an if/elif;
+                // otherwise the collected body belongs to `else`.
+                let (else_body, else_trim) = match cond {
+                    Some(cond) => {
+                        branches.push(Branch {
+                            cond,
+                            body,
+                            trim: arm_trim,
+                        });
+                        (None, Trim::default())
+                    }
+                    None => (Some(body), arm_trim),
+                };
+                self.cur = parent;
+                self.cur.push(Block::If {
+                    branches,
+                    else_body,
+                    else_trim,
+                    end_trim: trim,
+                });
+            }
+            "include" => {
+                let path = parse_string_arg("include", "path", arg)?;
+                self.cur.push(Block::Include {
+                    path,
+                    template: None,
+                    trim,
+                    span: tag,
+                });
+            }
+            "section" => {
+                let res = parse_section_arg(arg).and_then(|(name, mode)| {
+                    if self
+                        .stack
+                        .iter()
+                        .any(|f| matches!(f, Frame::Section { .. }))
+                    {
+                        return Err(Error::Template(format!(
+                            "section '{name}' cannot be nested in another section"
+                        )));
+                    }
+                    Ok((name, mode))
+                });
+                let (name, mode) = self.opener("section", tag, res)?;
+                self.stack.push(Frame::Section {
+                    parent: std::mem::take(&mut self.cur),
+                    name,
+                    mode,
+                    open: trim,
+                    span: tag,
+                });
+            }
+            "endsection" => {
+                expect_no_arg("endsection", arg)?;
+                let (parent, name, mode, open) = match self.stack.pop() {
+                    Some(Frame::Section {
+                        parent,
+                        name,
+                        mode,
+                        open,
+                        ..
+                    }) => (parent, name, mode, open),
+                    Some(f) if f.is_invalid("section") => return Ok(()),
+                    Some(other) => {
+                        self.stack.push(other);
+                        return Err(Error::Template("stray 'endsection'".into()));
+                    }
+                    None => return Err(Error::Template("stray 'endsection'".into())),
+                };
+                let body = std::mem::take(&mut self.cur);
+                self.cur = parent;
+                self.cur.push(Block::Section {
+                    name,
+                    mode,
+                    body,
+                    open,
+                    close: trim,
+                });
+            }
+            "extends" => {
+                if !self.stack.is_empty() {
+                    return Err(Error::Template("'extends' must be at the top level".into()));
+                }
+                if self.extends.is_some() {
+                    return Err(Error::Template("duplicate 'extends'".into()));
+                }
+                self.extends = Some(Extends {
+                    path: parse_string_arg("extends", "path", arg)?,
+                    parent: None,
+                    trim,
+                    span: tag,
+                });
+            }
+            "block" => {
+                let res = parse_name_arg("block", arg).and_then(|name| {
+                    if !self.slot_names.insert(name.clone()) {
+                        return Err(Error::Template(format!("duplicate block '{name}'")));
                     }
+                    Ok(name)
+                });
+                let name = self.opener("block", tag, res)?;
+                self.stack.push(Frame::Slot {
+                    parent: std::mem::take(&mut self.cur),
+                    name,
+                    open: trim,
+                    span: tag,
+                });
+            }
+            "endblock" => {
+                expect_no_arg("endblock", arg)?;
+                let (parent, name, open, span) = match self.stack.pop() {
+                    Some(Frame::Slot {
+                        parent,
+                        name,
+                        open,
+                        span,
+                    }) => (parent, name, open, span),
+                    Some(f) if f.is_invalid("block") => return Ok(()),
+                    Some(other) => {
+                        self.stack.push(other);
+                        return Err(Error::Template("stray 'endblock'".into()));
+                    }
+                    None => return Err(Error::Template("stray 'endblock'".into())),
+                };
+                let body = std::mem::take(&mut self.cur);
+                self.cur = parent;
+                self.cur.push(Block::Slot {
+                    name,
+                    body,
+                    open,
+                    close: trim,
+                    span,
+                });
+            }
+            "super" => {
+                expect_no_arg("super", arg)?;
+                if !self
+                    .stack
+                    .iter()
+                    .any(|f| matches!(f, Frame::Slot { .. }) || f.is_invalid("block"))
+                {
+                    return Err(Error::Template("'super' outside of a block".into()));
+                }
+                self.cur.push(Block::Super(trim));
+            }
+            "for" => {
+                let res = parse_for_arg(arg, arg_start).and_then(|(var, source)| {
+                    if let LoopSource::Files(pattern) = &source {
+                        check_refs(pattern, &bound_vars(&self.stack))?;
+                        if !pattern.contains("{{") {
+                            compile_glob("files", pattern)?;
+                        }
+                    }
+                    Ok((var, source))
+                });
+                let res = self.opener("for", tag, res);
+                if res.is_err()
+                    && let Some(Frame::Invalid { var, .. }) = self.stack.last_mut()
+                {
+                    *var = arg
+                        .split_whitespace()
+                        .next()
+                        .filter(|v| is_name(v))
+                        .map(str::to_string);
+                }
+                let (var, source) = res?;
+                self.stack.push(Frame::For {
+                    parent: std::mem::take(&mut self.cur),
+                    var,
+                    source,
+                    open: trim,
+                    span: tag,
+                });
+            }
+            "endfor" => {
+                expect_no_arg("endfor", arg)?;
+                let (parent, var, source, open, span) = match self.stack.pop() {
+                    Some(Frame::For {
+                        parent,
+                        var,
+                        source,
+                        open,
+                        span,
+                    }) => (parent, var, source, open, span),
+                    Some(f) if f.is_invalid("for") => return Ok(()),
+                    Some(other) => {
+                        self.stack.push(other);
+                        return Err(Error::Template("stray 'endfor'".into()));
+                    }
+                    None => return Err(Error::Template("stray 'endfor'".into())),
+                };
+                let body = std::mem::take(&mut self.cur);
+                self.cur = parent;
+                self.cur.push(Block::For {
+                    var,
+                    source,
+                    body,
+                    open,
+                    close: trim,
+                    span,
+                });
+            }
+            "define" => {
+                let def = match parse_define_arg(arg, arg_start, &self.definitions) {
+                    Ok(def) => Arc::new(def),
+                    Err(err) => {
+                        // A valid name whose expression is rejected is still
+                        // defined, so later uses are not also reported as
+                        // undefined. The template fails to parse, so the
+                        // stand-in is never evaluated.
+                        if let Ok((name, _)) = define_parts(arg, &self.definitions) {
+                            let def = Definition {
+                                id: NEXT_DEFINITION.fetch_add(1, Ordering::Relaxed),
+                                name: name.to_string(),
+                                expr: Expr::Matcher(Matcher::CargoWorkspace, tag),
+                            };
+                            self.definitions.insert(def.name.clone(), Arc::new(def));
+                        }
+                        return Err(err);
+                    }
+                };
+                self.definitions.insert(def.name.clone(), def.clone());
+                self.cur.push(Block::Define { def, trim });
+            }
+            "raw" => {
+                // The body is still kept verbatim so its contents do not
+                // raise knock-on errors.
+                if let Err(err) = expect_no_arg("raw", arg) {
+                    let err = self.locate(err, tag);
+                    self.errors.push(err);
                 }
-                self.idx = tag_start + 4; // after <!--
+                let close_start = self
+                    .find_endraw()
+                    .ok_or_else(|| Error::Template("unclosed 'raw' block".into()))?;
+                let text = self.src[self.idx..close_start].to_string();
+                self.idx = close_start + 4; // after <!--
+                let left = self.consume("-");
                 self.skip_ws();
-                if self.consume_if("if") {
-                    self.skip_ws();
-                    let expr_str = self.read_until("-->")?;
-                    let expr = ExprParser::new(expr_str.trim()).parse_expr()?;
-                    self.idx += 3; // -->
-                    stack.push((expr, std::mem::take(&mut cur)));
-                } else if self.consume_if("endif") {
-                    let tail = self.read_until("-->")?;
-                    let rest = tail.trim();
-                    if !rest.is_empty() {
-                        return Err(Error::Template("unexpected content after 'endif'".into()));
+                self.consume_keyword("endraw");
+                let (_, close_arg, right) = self.read_tag_body(close_start)?;
+                let close = Trim { left, right };
+                // The closing tag trims the body like the text before any
+                // other tag.
+                let n = self.cur.len();
+                self.cur.push(Block::Text(text));
+                self.apply_trim(close_start, close);
+                let text = if self.cur.len() > n {
+                    match self.cur.pop() {
+                        Some(Block::Text(t)) => t,
+                        _ => unreachable!("the body was just pushed"),
                     }
-                    self.idx += 3; // -->
-                    let (expr, parent) = match stack.pop() {
-                        Some(v) => v,
-                        None => return Err(Error::Template("stray 'endif'".into())),
-                    };
-                    let completed = Block::If {
-                        cond: expr,
-                        body: cur,
-                    };
-                    cur = parent;
-                    cur.push(completed);
                 } else {
-                    // literal comment
-                    let inner = self.read_until("-->")?;
-                    self.idx += 3; // -->
-                    let mut s = String::from("<!--");
-                    s.push_str(inner);
-                    s.push_str("-->");
-                    cur.push(Block::Text(s));
-                }
-            } else {
-                let txt = &self.src[self.idx..];
-                if !txt.is_empty() {
-                    cur.push(Block::Text(txt.to_string()))
+                    String::new()
+                };
+                self.cur.push(Block::Raw {
+                    text,
+                    open: trim,
+                    close,
+                });
+                expect_no_arg("endraw", close_arg)
+                    .map_err(|e| self.locate(e, Span::new(close_start, self.idx)))?;
+            }
+            "endraw" => return Err(Error::Template("stray 'endraw'".into())),
+            "once" => {
+                let res = parse_string_arg("once", "key", arg).and_then(|key| {
+                    check_refs(&key, &bound_vars(&self.stack))?;
+                    Ok(key)
+                });
+                let key = self.opener("once", tag, res)?;
+                self.stack.push(Frame::Once {
+                    parent: std::mem::take(&mut self.cur),
+                    key,
+                    open: trim,
+                    span: tag,
+                });
+            }
+            "endonce" => {
+                expect_no_arg("endonce", arg)?;
+                let (parent, key, open) = match self.stack.pop() {
+                    Some(Frame::Once {
+                        parent, key, open, ..
+                    }) => (parent, key, open),
+                    Some(f) if f.is_invalid("once") => return Ok(()),
+                    Some(other) => {
+                        self.stack.push(other);
+                        return Err(Error::Template("stray 'endonce'".into()));
+                    }
+                    None => return Err(Error::Template("stray 'endonce'".into())),
+                };
+                let body = std::mem::take(&mut self.cur);
+                self.cur = parent;
+                self.cur.push(Block::Once {
+                    key,
+                    body,
+                    open,
+                    close: trim,
+                });
+            }
+            _ => unreachable!("control_keyword returned {kw}"),
+        }
+        Ok(())
+    }
+
+    /// Push the template text between `start` and `end`, splitting out
+    /// interpolations. Code spans and fenced code are copied as they are.
+    fn text(&mut self, start: usize, end: usize) {
+        let vars = bound_vars(&self.stack);
+        let mut lit = String::new();
+        let mut from = start;
+        while let Some(open) = find_outside_code(self.src, from, "{{").filter(|&i| i < end) {
+            if self.src[..open].ends_with('\\') {
+                lit.push_str(&self.src[from..open - 1]);
+                lit.push_str("{{");
+                from = open + 2;
+                continue;
+            }
+            lit.push_str(&self.src[from..open]);
+            if !lit.is_empty() {
+                self.cur.push(Block::Text(std::mem::take(&mut lit)));
+            }
+            let close = self.src[open..end].find("}}").map_or(end, |i| open + i + 2);
+            let span = Span::new(open, close);
+            if let Err(e) = push_text(&mut self.cur, &self.src[open..close], span, &vars) {
+                let err = self.locate(e.offset(open), span);
+                self.errors.push(err);
+            }
+            from = close;
+        }
+        lit.push_str(&self.src[from..end]);
+        if !lit.is_empty() {
+            self.cur.push(Block::Text(lit));
+        }
+    }
+
+    /// The start of the `endraw` tag closing the `raw` block whose body
+    /// starts at the current position. `raw` tags before it are recorded as
+    /// errors, since raw blocks do not nest; comments that merely start with
+    /// the word, such as `<!-- raw output follows -->`, are part of the body.
+    fn find_endraw(&mut self) -> Option<usize> {
+        let body_start = self.idx;
+        let mut found = None;
+        while let Some(start) = self.src[self.idx..].find("<!--").map(|i| self.idx + i) {
+            self.idx = start + 4;
+            self.consume("-");
+            self.skip_ws();
+            if self.consume_keyword("endraw") {
+                found = Some(start);
+                break;
+            }
+            if self.consume_keyword("raw") {
+                let Ok((_, arg, _)) = self.read_tag_body(start) else {
+                    break;
+                };
+                if !arg.trim().is_empty() {
+                    continue;
                 }
-                self.idx = self.src.len();
+                let err = Error::Template("'raw' blocks cannot be nested".into());
+                let err = self.locate(err, Span::new(start, self.idx));
+                self.errors.push(err);
             }
         }
+        self.idx = body_start;
+        found
+    }
+
+    /// Pass `res` through; on error, push a placeholder frame for the `kw`
+    /// block that `tag` opens so its closing tag still matches.
+    fn opener<T>(
+        &mut self,
+        kw: &'static str,
+        tag: Span,
+        res: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if res.is_err() {
+            self.stack.push(Frame::Invalid {
+                kw,
+                span: tag,
+                var: None,
+            });
+        }
+        res
+    }
+
+    /// Attach `span` (when the error has none yet) and this template's source.
+    fn locate(&self, err: Error, span: Span) -> Error {
+        err.or_at(span).in_source(self.source)
+    }
+
+    /// Consume a control keyword at the current position, if there is one.
+    fn control_keyword(&mut self) -> Option<&'static str> {
+        CONTROL_KEYWORDS
+            .iter()
+            .copied()
+            .find(|kw| self.consume_keyword(kw))
+    }
+
+    /// Read the rest of the tag opened at `tag_start` up to and including
+    /// `-->`. Returns the offset and text of the tag's argument and whether it
+    /// closed with the `--->` trim marker.
+    fn read_tag_body(&mut self, tag_start: usize) -> Result<(usize, &'a str, bool), Error> {
+        let start = self.idx;
+        let body = self
+            .read_until("-->")
+            .map_err(|e| self.locate(e, Span::new(tag_start, self.src.len())))?;
+        self.idx += 3; // -->
+        match body.strip_suffix('-') {
+            Some(b) => Ok((start, b, true)),
+            None => Ok((start, body, false)),
+        }
+    }
 
-        if !stack.is_empty() {
-            return Err(Error::Template("unclosed 'if' block".into()));
+    /// Apply whitespace control for the tag that starts at `start` and ends at
+    /// the current position.
+    fn apply_trim(&mut self, start: usize, trim: Trim) {
+        if self.opts.lossless {
+            return;
+        }
+        let standalone = self.opts.trim_tag_lines && self.is_standalone(start);
+        if (trim.left || standalone)
+            && let Some(Block::Text(t)) = self.cur.last_mut()
+        {
+            t.truncate(t.trim_end_matches([' ', '\t']).len());
+            if trim.left {
+                let nl = if t.ends_with("\r\n") {
+                    2
+                } else {
+                    usize::from(t.ends_with('\n'))
+                };
+                t.truncate(t.len() - nl);
+            }
+            if t.is_empty() {
+                self.cur.pop();
+            }
         }
+        if trim.right || standalone {
+            let rest = &self.src[self.idx..];
+            let ws = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+            let after = &rest[ws..];
+            if let Some(nl) = ["\r\n", "\n"].iter().find(|nl| after.starts_with(**nl)) {
+                self.idx += ws + nl.len();
+            } else if after.is_empty() {
+                self.idx += ws;
+            }
+        }
+    }
 
-        Ok(Template { blocks: cur })
+    /// Whether the tag spanning `start..self.idx` is alone on its line apart
+    /// from spaces and tabs.
+    fn is_standalone(&self, start: usize) -> bool {
+        let line_start = self.src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let before = &self.src[line_start..start];
+        let after = self.src[self.idx..].trim_start_matches([' ', '\t']);
+        before.chars().all(|c| c == ' ' || c == '\t')
+            && (after.is_empty() || after.starts_with('\n') || after.starts_with("\r\n"))
     }
 
-    fn find(&self, needle: &str) -> Option<usize> {
-        self.src[self.idx..].find(needle).map(|i| self.idx + i)
+    fn consume(&mut self, s: &str) -> bool {
+        if self.src[self.idx..].starts_with(s) {
+            self.idx += s.len();
+            true
+        } else {
+            false
+        }
     }
 
     fn skip_ws(&mut self) {
@@ -92,13 +891,20 @@ impl<'a> TemplateParser<'a> {
         }
     }
 
-    fn consume_if(&mut self, s: &str) -> bool {
-        if self.src[self.idx..].starts_with(s) {
-            self.idx += s.len();
-            true
-        } else {
-            false
+    /// Consume `kw` only when it is not immediately followed by an identifier
+    /// character, so `else` does not match the start of `elsewhere`.
+    fn consume_keyword(&mut self, kw: &str) -> bool {
+        let rest = &self.src[self.idx..];
+        if !rest.starts_with(kw) {
+            return false;
+        }
+        if let Some(next) = rest[kw.len()..].chars().next()
+            && (next.is_alphanumeric() || next == '_')
+        {
+            return false;
         }
+        self.idx += kw.len();
+        true
     }
 
     fn read_until(&mut self, delim: &str) -> Result<&'a str, Error> {
@@ -121,20 +927,103 @@ impl<'a> TemplateParser<'a> {
 struct ExprParser<'a> {
     src: &'a str,
     idx: usize,
+    /// Offset of `src` within the template, for spans.
+    base: usize,
+    /// Names that may stand for a condition, when parsing one.
+    definitions: Option<&'a Definitions>,
+    /// The name being defined, which its own definition may not use.
+    defining: Option<&'a str>,
+    /// Errors in operands that were skipped so parsing could continue.
+    errors: Vec<Error>,
 }
 
 impl<'a> ExprParser<'a> {
-    fn new(src: &'a str) -> Self {
-        Self { src, idx: 0 }
+    fn new(src: &'a str, base: usize) -> Self {
+        Self {
+            src,
+            idx: 0,
+            base,
+            definitions: None,
+            defining: None,
+            errors: Vec::new(),
+        }
     }
 
     fn parse_expr(mut self) -> Result<Expr, Error> {
-        let expr = self.parse_or()?;
-        self.skip_ws();
-        if self.idx != self.src.len() {
-            return Err(Error::Template("trailing characters in expression".into()));
+        let res = self.parse_or().and_then(|expr| {
+            self.skip_ws();
+            if self.idx != self.src.len() {
+                return Err(Error::Template("trailing characters in expression".into()));
+            }
+            Ok(expr)
+        });
+        let mut errors = std::mem::take(&mut self.errors);
+        match res {
+            Ok(expr) => Error::from_all(errors).map(|()| expr),
+            Err(e) => {
+                errors.push(e.or_at(self.span_here()));
+                Error::from_all(errors).map(|()| unreachable!("an error was just recorded"))
+            }
+        }
+    }
+
+    /// Parse one operand of `&&`. A bad operand is recorded and skipped up to
+    /// the next `&&`, `||` or unmatched `)`, so the rest of the expression is
+    /// still checked.
+    fn operand(&mut self) -> Result<Expr, Error> {
+        let start = self.idx;
+        match self.parse_not() {
+            Ok(e) => Ok(e),
+            Err(e) => {
+                self.errors.push(e.or_at(self.span_here()));
+                self.idx = start;
+                self.skip_operand();
+                // Never evaluated: `parse_expr` fails once an error is recorded.
+                let span = self.span(start, self.idx);
+                Ok(Expr::Matcher(Matcher::EnvExists(String::new()), span))
+            }
+        }
+    }
+
+    fn skip_operand(&mut self) {
+        let mut depth = 0usize;
+        let mut quote = None;
+        while let Some(ch) = self.peek() {
+            match (quote, ch) {
+                (Some(_), '\\') => {
+                    self.idx += 1;
+                    if let Some(next) = self.peek() {
+                        self.idx += next.len_utf8();
+                    }
+                    continue;
+                }
+                (Some(q), _) if ch == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(ch),
+                (None, '(') => depth += 1,
+                (None, ')') if depth == 0 => return,
+                (None, ')') => depth -= 1,
+                (None, _)
+                    if depth == 0
+                        && (self.src[self.idx..].starts_with("&&")
+                            || self.src[self.idx..].starts_with("||")) =>
+                {
+                    return;
+                }
+                (None, _) => {}
+            }
+            self.idx += ch.len_utf8();
         }
-        Ok(expr)
+    }
+
+    /// Span of the character at the current position, where parsing stopped.
+    fn span_here(&self) -> Span {
+        let len = self.peek().map_or(0, char::len_utf8);
+        self.span(self.idx, self.idx + len)
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(self.base + start, self.base + end)
     }
 
     fn parse_or(&mut self) -> Result<Expr, Error> {
@@ -152,11 +1041,11 @@ impl<'a> ExprParser<'a> {
     }
 
     fn parse_and(&mut self) -> Result<Expr, Error> {
-        let mut left = self.parse_not()?;
+        let mut left = self.operand()?;
         loop {
             self.skip_ws();
             if self.consume("&&") {
-                let right = self.parse_not()?;
+                let right = self.operand()?;
                 left = Expr::And(Box::new(left), Box::new(right));
             } else {
                 break;
@@ -186,13 +1075,48 @@ impl<'a> ExprParser<'a> {
             return Ok(e);
         }
 
+        let start = self.idx;
         if self.consume_ident("exists") {
             let arg = self.parse_paren_string()?;
-            return Ok(Expr::Matcher(Matcher::Exists(arg)));
+            let span = self.span(start, self.idx);
+            return Ok(Expr::Matcher(Matcher::Exists(arg), span));
         }
         if self.consume_ident("lang") {
             let arg = self.parse_paren_string()?;
-            return Ok(Expr::Matcher(Matcher::Lang(arg)));
+            let span = self.span(start, self.idx);
+            return Ok(Expr::Matcher(Matcher::Lang(arg), span));
+        }
+        if self.consume_ident("contains") {
+            let m = self.parse_contains_args()?;
+            return Ok(Expr::Matcher(m, self.span(start, self.idx)));
+        }
+        if self.consume_ident("cargo_workspace") {
+            self.skip_ws();
+            if !self.consume("(") {
+                return Err(Error::Template("expected '(' after cargo_workspace".into()));
+            }
+            self.skip_ws();
+            if !self.consume(")") {
+                return Err(Error::Template("cargo_workspace takes no arguments".into()));
+            }
+            return Ok(Expr::Matcher(
+                Matcher::CargoWorkspace,
+                self.span(start, self.idx),
+            ));
+        }
+        if self.consume_ident("cargo_edition") {
+            let arg = self.parse_paren_string()?;
+            let span = self.span(start, self.idx);
+            return Ok(Expr::Matcher(Matcher::CargoEdition(arg), span));
+        }
+        if self.consume_ident("cargo_feature") {
+            let arg = self.parse_paren_string()?;
+            let span = self.span(start, self.idx);
+            return Ok(Expr::Matcher(Matcher::CargoFeature(arg), span));
+        }
+        if self.consume_ident("dep") {
+            let m = self.parse_dep_args()?;
+            return Ok(Expr::Matcher(m, self.span(start, self.idx)));
         }
         if self.consume_ident("env") {
             self.skip_ws();
@@ -207,10 +1131,63 @@ impl<'a> ExprParser<'a> {
                 Some(v) => Matcher::EnvEquals { name, value: v },
                 None => Matcher::EnvExists(name),
             };
-            return Ok(Expr::Matcher(m));
+            return Ok(Expr::Matcher(m, self.span(start, self.idx)));
+        }
+
+        let name = self.parse_word();
+        self.skip_ws();
+        if name.is_empty() || self.peek() == Some('(') {
+            self.idx = start;
+            return Err(Error::Template("expected matcher or '('".into()));
+        }
+        let span = self.span(start, start + name.len());
+        if self.defining == Some(name) {
+            let msg = format!("recursive definition of '{name}'");
+            return Err(Error::Template(msg).or_at(span));
+        }
+        match self.definitions.and_then(|d| d.get(name)) {
+            Some(def) => Ok(Expr::Ref(def.clone(), span)),
+            None => Err(Error::Template(format!("undefined condition '{name}'")).or_at(span)),
+        }
+    }
+
+    /// Consume a name made of letters, digits and `_`, which may be empty.
+    fn parse_word(&mut self) -> &'a str {
+        let start = self.idx;
+        while let Some(ch) = self.peek() {
+            if ch.is_alphanumeric() || ch == '_' {
+                self.idx += ch.len_utf8();
+            } else {
+                break;
+            }
         }
+        &self.src[start..self.idx]
+    }
 
-        Err(Error::Template("expected matcher or '('".into()))
+    fn parse_for_arg(&mut self) -> Result<(String, LoopSource), Error> {
+        let var = self.parse_ident()?;
+        if var.contains('.') {
+            return Err(Error::Template(format!("invalid loop variable: {var}")));
+        }
+        self.skip_ws();
+        if !self.consume_ident("in") {
+            return Err(Error::Template("expected 'in' after loop variable".into()));
+        }
+        self.skip_ws();
+        let source = if self.consume_ident("files") {
+            LoopSource::Files(self.parse_paren_string()?)
+        } else if self.consume_ident("languages") {
+            LoopSource::Languages
+        } else {
+            return Err(Error::Template(
+                "expected 'files(GLOB)' or 'languages' after 'in'".into(),
+            ));
+        };
+        self.skip_ws();
+        if self.idx != self.src.len() {
+            return Err(Error::Template("trailing characters in 'for' tag".into()));
+        }
+        Ok((var, source))
     }
 
     fn parse_paren_string(&mut self) -> Result<String, Error> {
@@ -227,7 +1204,87 @@ impl<'a> ExprParser<'a> {
         Ok(s)
     }
 
+    /// Parse `(GLOB, PATTERN[, regex])` after `contains`.
+    fn parse_contains_args(&mut self) -> Result<Matcher, Error> {
+        self.skip_ws();
+        if !self.consume("(") {
+            return Err(Error::Template("expected '(' after contains".into()));
+        }
+        let glob = self.parse_string_until(&[')', ','])?;
+        self.skip_ws();
+        if !self.consume(",") {
+            return Err(Error::Template(
+                "contains requires a glob and a pattern".into(),
+            ));
+        }
+        self.skip_ws();
+        let pattern = self.parse_string_until(&[')', ','])?;
+        self.skip_ws();
+        let regex = self.consume(",");
+        if regex {
+            self.skip_ws();
+            if !self.consume_ident("regex") {
+                return Err(Error::Template(
+                    "expected 'regex' as the third contains argument".into(),
+                ));
+            }
+            self.skip_ws();
+        }
+        if !self.consume(")") {
+            return Err(Error::Template("expected ')'".into()));
+        }
+        Ok(Matcher::Contains {
+            glob,
+            pattern,
+            regex,
+        })
+    }
+
+    /// Parse `([ECO:]NAME[, dev])` after `dep`.
+    fn parse_dep_args(&mut self) -> Result<Matcher, Error> {
+        self.skip_ws();
+        if !self.consume("(") {
+            return Err(Error::Template("expected '(' after dep".into()));
+        }
+        let arg = self.parse_string_until(&[')', ','])?;
+        let (eco, name) = match arg.split_once(':') {
+            Some((eco, name)) => {
+                let eco = Ecosystem::parse(eco).ok_or_else(|| {
+                    Error::Template(format!(
+                        "unknown ecosystem '{eco}'; expected cargo, npm, python or go"
+                    ))
+                })?;
+                (Some(eco), name.to_string())
+            }
+            None => (None, arg),
+        };
+        if name.is_empty() {
+            return Err(Error::Template("dep requires a package name".into()));
+        }
+        self.skip_ws();
+        let dev = self.consume(",");
+        if dev {
+            self.skip_ws();
+            if !self.consume_ident("dev") {
+                return Err(Error::Template(
+                    "expected 'dev' as the second dep argument".into(),
+                ));
+            }
+            self.skip_ws();
+        }
+        if !self.consume(")") {
+            return Err(Error::Template("expected ')'".into()));
+        }
+        Ok(Matcher::Dep { eco, name, dev })
+    }
+
     fn parse_string_like(&mut self) -> Result<String, Error> {
+        self.parse_string_until(&[')'])
+    }
+
+    /// Parse a quoted or raw string, or a bare token ending at whitespace or
+    /// one of `stops`.
+    fn parse_string_until(&mut self, stops: &[char]) -> Result<String, Error> {
         self.skip_ws();
         if self.peek() == Some('"') || self.peek() == Some('\'') {
             self.parse_quoted_string()
@@ -237,7 +1294,7 @@ impl<'a> ExprParser<'a> {
         } else {
             let start = self.idx;
             while let Some(ch) = self.peek() {
-                if ch.is_whitespace() || ch == ')' {
+                if ch.is_whitespace() || stops.contains(&ch) {
                     break;
                 }
                 self.idx += ch.len_utf8();
@@ -296,6 +1353,22 @@ impl<'a> ExprParser<'a> {
         Err(Error::Template("unterminated raw string".into()))
     }
 
+    fn parse_ident(&mut self) -> Result<String, Error> {
+        self.skip_ws();
+        let start = self.idx;
+        while let Some(ch) = self.peek() {
+            if ch.is_alphanumeric() || ch == '_' || ch == '.' {
+                self.idx += ch.len_utf8();
+            } else {
+                break;
+            }
+        }
+        if self.idx == start {
+            return Err(Error::Template("expected identifier".into()));
+        }
+        Ok(self.src[start..self.idx].to_string())
+    }
+
     fn consume_ident(&mut self, ident: &str) -> bool {
         let mut i = self.idx;
         for ch in ident.chars() {
@@ -355,19 +1428,245 @@ impl<'a> ExprParser<'a> {
     }
 }
 
-fn parse_env_arg(s: &str) -> Result<(String, Option<String>), Error> {
-    let s = s.trim();
-    if s.is_empty() {
-        return Err(Error::Template("empty env() argument".into()));
-    }
-    let (name, mut i) = parse_token(s)?;
-    if name.is_empty() {
-        return Err(Error::Template("empty env var name".into()));
+/// Push `txt`, the `{{ … }}` at `span`, onto `cur` as text and interpolation
+/// blocks. Error spans are relative to `txt`.
+fn push_text(
+    cur: &mut Vec<Block>,
+    txt: &str,
+    span: Span,
+    vars: &[(&str, VarKind)],
+) -> Result<(), Error> {
+    for piece in split_interpolations(txt)? {
+        match piece {
+            Piece::Lit(s) => cur.push(Block::Text(s)),
+            Piece::Ref(name) => {
+                // `name` borrows from `txt`, so its address gives its offset.
+                let at = name.as_ptr() as usize - txt.as_ptr() as usize;
+                let fact = Fact::parse_in(name, vars)
+                    .map_err(|e| e.or_at(Span::new(at, at + name.len())))?;
+                cur.push(Block::Interpolate { fact, span });
+            }
+        }
     }
-    while let Some(ch) = s[i..].chars().next() {
-        if ch.is_whitespace() {
-            i += ch.len_utf8();
-        } else {
+    Ok(())
+}
+
+/// Parse the expression of an `if`/`elif` tag whose argument `s` starts at
+/// byte `base`. Interpolations in matcher arguments must refer to `vars` or
+/// facts, and the remaining arguments must pass [`Expr::validate`]. Bare
+/// names refer to `definitions`, except `defining`, which is being defined.
+fn parse_tag_expr(
+    s: &str,
+    base: usize,
+    vars: &[(&str, VarKind)],
+    definitions: &Definitions,
+    defining: Option<&str>,
+) -> Result<Expr, Error> {
+    let lead = s.len() - s.trim_start().len();
+    let mut ep = ExprParser::new(s.trim(), base + lead);
+    ep.definitions = Some(definitions);
+    ep.defining = defining;
+    let expr = ep.parse_expr()?;
+    expr.map_args(&mut |s| check_refs(s, vars).map(|_| s.to_string()))?;
+    expr.validate()?;
+    Ok(expr)
+}
+
+/// Parse `NAME = EXPR` from a `define` tag whose argument `s` starts at byte
+/// `base`. The expression may use earlier definitions and facts, but not
+/// loop variables, so its value is the same wherever it is used.
+fn parse_define_arg(s: &str, base: usize, definitions: &Definitions) -> Result<Definition, Error> {
+    let (name, expr) = define_parts(s, definitions)?;
+    let expr_base = base + s.len() - expr.len();
+    let expr = parse_tag_expr(expr, expr_base, &[], definitions, Some(name))?;
+    Ok(Definition {
+        id: NEXT_DEFINITION.fetch_add(1, Ordering::Relaxed),
+        name: name.to_string(),
+        expr,
+    })
+}
+
+/// Split a `define` argument into its name and expression text, checking
+/// that the name is valid and not yet taken.
+fn define_parts<'s>(s: &'s str, definitions: &Definitions) -> Result<(&'s str, &'s str), Error> {
+    let Some((name, expr)) = s.split_once('=') else {
+        return Err(Error::Template("define requires 'NAME = EXPR'".into()));
+    };
+    let name = name.trim();
+    let mut chars = name.chars();
+    let starts_ok = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_');
+    if !starts_ok || !chars.all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(Error::Template(format!("invalid definition name: {name}")));
+    }
+    if MATCHER_NAMES.contains(&name) {
+        return Err(Error::Template(format!(
+            "'{name}' is a matcher and cannot be defined"
+        )));
+    }
+    if definitions.contains_key(name) {
+        return Err(Error::Template(format!("duplicate definition '{name}'")));
+    }
+    Ok((name, expr))
+}
+
+/// Whether a comment starting with keyword `kw` and argument `arg`, which
+/// starts at byte `arg_start` of `src`, is a control tag. `if`, `elif`,
+/// `else` and the closing tags always are. Others are tags when `arg` parses,
+/// looks like tag syntax, or, for an opener, its closing tag follows; the
+/// rest is prose such as `<!-- for details see below -->`, kept as text.
+fn is_tag(src: &str, kw: &str, arg: &str, arg_start: usize) -> bool {
+    let parses = match kw {
+        "include" | "extends" => parse_string_arg(kw, "path", arg).is_ok(),
+        "once" => parse_string_arg(kw, "key", arg).is_ok(),
+        "section" => parse_section_arg(arg).is_ok(),
+        "block" => parse_name_arg(kw, arg).is_ok(),
+        "for" => parse_for_arg(arg, arg_start).is_ok(),
+        "super" | "raw" => arg.trim().is_empty(),
+        "define" => false,
+        _ => true,
+    };
+    if parses || looks_like_tag_syntax(arg) {
+        return true;
+    }
+    let after = arg_start + arg.len();
+    match kw {
+        // `base.md` or `docs/x` is a path even without quotes.
+        "include" | "extends" => arg.split_whitespace().next().is_some_and(|w| {
+            w.contains('/')
+                || w.rsplit_once('.').is_some_and(|(stem, ext)| {
+                    !stem.is_empty() && !ext.is_empty() && ext.chars().all(char::is_alphanumeric)
+                })
+        }),
+        "for" => closed_later(src, after, "endfor"),
+        "section" => closed_later(src, after, "endsection"),
+        "block" => closed_later(src, after, "endblock"),
+        "once" => closed_later(src, after, "endonce"),
+        "raw" => closed_later(src, after, "endraw"),
+        _ => false,
+    }
+}
+
+/// Whether `arg` holds a string, a call, a definition or a loop: syntax that
+/// prose rarely uses. A `'` counts only at the start of a word, so
+/// apostrophes do not.
+fn looks_like_tag_syntax(arg: &str) -> bool {
+    arg.contains(['"', '(', ')', '='])
+        || arg
+            .split_whitespace()
+            .any(|w| w == "in" || w.starts_with('\''))
+}
+
+/// Whether a `closer` tag appears in `src` after byte `from`, outside code.
+fn closed_later(src: &str, from: usize, closer: &str) -> bool {
+    let mut pos = from;
+    while let Some(start) = find_outside_code(src, pos, "<!--") {
+        let rest = src[start + 4..].trim_start_matches('-').trim_start();
+        if let Some(after) = rest.strip_prefix(closer)
+            && !after.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        {
+            return true;
+        }
+        pos = start + 4;
+    }
+    false
+}
+
+/// Check that a tag that takes no arguments has none.
+fn expect_no_arg(tag: &str, s: &str) -> Result<(), Error> {
+    if !s.trim().is_empty() {
+        return Err(Error::Template(format!("unexpected content after '{tag}'")));
+    }
+    Ok(())
+}
+
+/// Parse `NAME [replace|append|prepend]` from a `section` tag.
+fn parse_section_arg(s: &str) -> Result<(String, SectionMode), Error> {
+    let mut parts = s.split_whitespace();
+    let name = parts
+        .next()
+        .ok_or_else(|| Error::Template("section requires a name".into()))?;
+    if !is_name(name) {
+        return Err(Error::Template(format!("invalid section name: {name}")));
+    }
+    let mode = match parts.next() {
+        None | Some("replace") => SectionMode::Replace,
+        Some("append") => SectionMode::Append,
+        Some("prepend") => SectionMode::Prepend,
+        Some(other) => {
+            return Err(Error::Template(format!(
+                "unknown section mode '{other}'; expected replace, append or prepend"
+            )));
+        }
+    };
+    if parts.next().is_some() {
+        return Err(Error::Template(format!(
+            "unexpected content after section '{name}'"
+        )));
+    }
+    Ok((name.to_string(), mode))
+}
+
+/// Parse the single string argument, described as `what`, of an `include`,
+/// `extends` or `once` tag.
+fn parse_string_arg(tag: &str, what: &str, s: &str) -> Result<String, Error> {
+    let mut ep = ExprParser::new(s.trim(), 0);
+    if ep.src.is_empty() {
+        return Err(Error::Template(format!("{tag} requires a {what}")));
+    }
+    let arg = ep.parse_string_like()?;
+    ep.skip_ws();
+    if ep.idx != ep.src.len() {
+        return Err(Error::Template(format!(
+            "unexpected content after {tag} {what}"
+        )));
+    }
+    Ok(arg)
+}
+
+/// Parse `VAR in files(GLOB)` or `VAR in languages` from a `for` tag whose
+/// argument `s` starts at byte `base`.
+fn parse_for_arg(s: &str, base: usize) -> Result<(String, LoopSource), Error> {
+    let lead = s.len() - s.trim_start().len();
+    let mut ep = ExprParser::new(s.trim(), base + lead);
+    let res = ep.parse_for_arg();
+    res.map_err(|e| e.or_at(ep.span_here()))
+}
+
+/// Parse the identifier argument of a `block` tag.
+fn parse_name_arg(tag: &str, s: &str) -> Result<String, Error> {
+    let mut parts = s.split_whitespace();
+    let name = parts
+        .next()
+        .ok_or_else(|| Error::Template(format!("{tag} requires a name")))?;
+    if !is_name(name) {
+        return Err(Error::Template(format!("invalid {tag} name: {name}")));
+    }
+    if parts.next().is_some() {
+        return Err(Error::Template(format!(
+            "unexpected content after {tag} '{name}'"
+        )));
+    }
+    Ok(name.to_string())
+}
+
+fn is_name(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_env_arg(s: &str) -> Result<(String, Option<String>), Error> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(Error::Template("empty env() argument".into()));
+    }
+    let (name, mut i) = parse_token(s)?;
+    if name.is_empty() {
+        return Err(Error::Template("empty env var name".into()));
+    }
+    while let Some(ch) = s[i..].chars().next() {
+        if ch.is_whitespace() {
+            i += ch.len_utf8();
+        } else {
             break;
         }
     }
@@ -390,7 +1689,7 @@ fn parse_token(s: &str) -> Result<(String, usize), Error> {
     if s.is_empty() {
         return Ok((String::new(), 0));
     }
-    let mut ep = ExprParser::new(s);
+    let mut ep = ExprParser::new(s, 0);
     if ep.peek() == Some('"') || ep.peek() == Some('\'') {
         let v = ep.parse_quoted_string()?;
         return Ok((v, ep.idx));
@@ -414,9 +1713,20 @@ fn parse_token(s: &str) -> Result<(String, usize), Error> {
 mod tests {
     use super::*;
 
+    fn parse(input: &str, opts: ParseOptions) -> Result<Template, Error> {
+        let source = Source {
+            path: None,
+            text: input.to_string(),
+        };
+        let (tpl, errors) = parse_template(&Arc::new(source), opts);
+        Error::from_all(errors).map(|()| tpl)
+    }
+
     #[derive(Debug)]
     enum Check {
         BlocksLen(usize),
+        /// Number of `if`/`elif` arms and presence of `else` on the first block.
+        IfShape(usize, bool),
         HasMatcher(Matcher),
         HasText(&'static str),
     }
@@ -424,11 +1734,36 @@ mod tests {
     fn ast_contains_matcher(blocks: &[Block], target: &Matcher) -> bool {
         for b in blocks {
             match b {
-                Block::Text(_) => {}
-                Block::If { cond, body } => {
-                    if expr_contains_matcher(cond, target) {
+                Block::Text(_)
+                | Block::Interpolate { .. }
+                | Block::Comment { .. }
+                | Block::Define { .. }
+                | Block::Raw { .. }
+                | Block::Include { .. }
+                | Block::Super(_) => {}
+                Block::If {
+                    branches,
+                    else_body,
+                    ..
+                } => {
+                    for br in branches {
+                        if expr_contains_matcher(&br.cond, target) {
+                            return true;
+                        }
+                        if ast_contains_matcher(&br.body, target) {
+                            return true;
+                        }
+                    }
+                    if let Some(body) = else_body
+                        && ast_contains_matcher(body, target)
+                    {
                         return true;
                     }
+                }
+                Block::Section { body, .. }
+                | Block::Slot { body, .. }
+                | Block::Once { body, .. }
+                | Block::For { body, .. } => {
                     if ast_contains_matcher(body, target) {
                         return true;
                     }
@@ -440,11 +1775,12 @@ mod tests {
 
     fn expr_contains_matcher(expr: &Expr, target: &Matcher) -> bool {
         match expr {
-            Expr::Matcher(m) => m == target,
+            Expr::Matcher(m, _) => m == target,
             Expr::And(a, b) | Expr::Or(a, b) => {
                 expr_contains_matcher(a, target) || expr_contains_matcher(b, target)
             }
             Expr::Not(e) => expr_contains_matcher(e, target),
+            Expr::Ref(def, _) => expr_contains_matcher(&def.expr, target),
         }
     }
 
@@ -456,7 +1792,33 @@ mod tests {
                         return true;
                     }
                 }
-                Block::If { body, .. } => {
+                Block::Interpolate { .. }
+                | Block::Comment { .. }
+                | Block::Define { .. }
+                | Block::Raw { .. }
+                | Block::Include { .. }
+                | Block::Super(_) => {}
+                Block::If {
+                    branches,
+                    else_body,
+                    ..
+                } => {
+                    if branches
+                        .iter()
+                        .any(|br| ast_contains_text(&br.body, needle))
+                    {
+                        return true;
+                    }
+                    if let Some(body) = else_body
+                        && ast_contains_text(body, needle)
+                    {
+                        return true;
+                    }
+                }
+                Block::Section { body, .. }
+                | Block::Slot { body, .. }
+                | Block::Once { body, .. }
+                | Block::For { body, .. } => {
                     if ast_contains_text(body, needle) {
                         return true;
                     }
@@ -470,12 +1832,123 @@ mod tests {
         for c in checks {
             match c {
                 Check::BlocksLen(n) => assert_eq!(template.blocks.len(), *n),
+                Check::IfShape(n, has_else) => match &template.blocks[0] {
+                    Block::If {
+                        branches,
+                        else_body,
+                        ..
+                    } => {
+                        assert_eq!(branches.len(), *n);
+                        assert_eq!(else_body.is_some(), *has_else);
+                    }
+                    other => panic!("expected if block, got {other:?}"),
+                },
                 Check::HasMatcher(m) => assert!(ast_contains_matcher(&template.blocks, m)),
                 Check::HasText(s) => assert!(ast_contains_text(&template.blocks, s)),
             }
         }
     }
 
+    #[test]
+    fn trim_markers_are_recorded() {
+        let src = "A\n<!--- for f in files(\"*.md\") --->\n{{ f }}\n<!-- endfor --->\n";
+        let tpl = parse(src, ParseOptions::default()).unwrap();
+        assert_eq!(tpl.blocks.len(), 2);
+        assert_eq!(tpl.blocks[0], Block::Text("A".into()));
+        match &tpl.blocks[1] {
+            Block::For {
+                open, close, body, ..
+            } => {
+                assert_eq!(
+                    *open,
+                    Trim {
+                        left: true,
+                        right: true
+                    }
+                );
+                assert_eq!(
+                    *close,
+                    Trim {
+                        left: false,
+                        right: true
+                    }
+                );
+                assert_eq!(body.last(), Some(&Block::Text("\n".into())));
+            }
+            other => panic!("expected for block, got {other:?}"),
+        }
+
+        // Lossless parsing records the markers but keeps the text as written.
+        let opts = ParseOptions {
+            trim_tag_lines: true,
+            lossless: true,
+        };
+        let tpl = parse(src, opts).unwrap();
+        assert_eq!(tpl.blocks[0], Block::Text("A\n".into()));
+        match &tpl.blocks[1] {
+            Block::For { open, body, .. } => {
+                assert!(open.left && open.right);
+                assert_eq!(body.first(), Some(&Block::Text("\n".into())));
+            }
+            other => panic!("expected for block, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn errors_point_at_their_source() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            /// The text the error's span covers.
+            at: &'static str,
+        }
+        let cases = vec![
+            Case {
+                name: "stray tag",
+                input: "a\nb <!-- endif --> c",
+                at: "<!-- endif -->",
+            },
+            Case {
+                name: "unclosed block points at its opening tag",
+                input: "x <!-- for f in languages -->\n{{ f }}\n",
+                at: "<!-- for f in languages -->",
+            },
+            Case {
+                name: "expression error at the offending character",
+                input: "<!-- if exists(a) ^ env(B) -->x<!-- endif -->",
+                at: "^",
+            },
+            Case {
+                name: "for argument error",
+                input: "<!-- for f on languages -->x<!-- endfor -->",
+                at: "o",
+            },
+            Case {
+                name: "undefined condition",
+                input: "<!-- define a = env(A) -->\n<!-- if a || bee -->x<!-- endif -->",
+                at: "bee",
+            },
+            Case {
+                name: "unknown fact",
+                input: "Name: {{ project.nmae }}\n",
+                at: "project.nmae",
+            },
+            Case {
+                name: "unterminated interpolation",
+                input: "ok\nthen {{ oops\n",
+                at: "{{",
+            },
+        ];
+        for c in cases {
+            let err = parse(c.input, ParseOptions::default()).unwrap_err();
+            let Error::Located(d) = err else {
+                panic!("{}: unlocated error {err:?}", c.name);
+            };
+            assert_eq!(&c.input[d.span.start..d.span.end], c.at, "case: {}", c.name);
+            assert!(d.source.is_some(), "case: {}", c.name);
+        }
+    }
+
     #[test]
     fn test_parse_success() {
         struct Case {
@@ -508,11 +1981,141 @@ mod tests {
                     Check::HasText("B"),
                 ],
             },
+            Case {
+                name: "contains",
+                input: "<!-- if contains(Cargo.toml, \"[workspace]\") || contains('src/{a,b}.rs', r\"^#!\\[no_std\\]\", regex) -->x<!-- endif -->",
+                checks: vec![
+                    Check::HasMatcher(Matcher::Contains {
+                        glob: "Cargo.toml".into(),
+                        pattern: "[workspace]".into(),
+                        regex: false,
+                    }),
+                    Check::HasMatcher(Matcher::Contains {
+                        glob: "src/{a,b}.rs".into(),
+                        pattern: "^#!\\[no_std\\]".into(),
+                        regex: true,
+                    }),
+                ],
+            },
+            Case {
+                name: "dep",
+                input: "<!-- if dep(tokio) && !dep(npm:@types/node, dev) -->x<!-- endif -->",
+                checks: vec![
+                    Check::HasMatcher(Matcher::Dep {
+                        eco: None,
+                        name: "tokio".into(),
+                        dev: false,
+                    }),
+                    Check::HasMatcher(Matcher::Dep {
+                        eco: Some(Ecosystem::Npm),
+                        name: "@types/node".into(),
+                        dev: true,
+                    }),
+                ],
+            },
+            Case {
+                name: "cargo matchers",
+                input: "<!-- if cargo_workspace( ) && cargo_edition(2024) || cargo_feature(\"std\") -->x<!-- endif -->",
+                checks: vec![
+                    Check::HasMatcher(Matcher::CargoWorkspace),
+                    Check::HasMatcher(Matcher::CargoEdition("2024".into())),
+                    Check::HasMatcher(Matcher::CargoFeature("std".into())),
+                ],
+            },
             Case {
                 name: "complex expr",
                 input: "<!-- if env(CI) && !env(NODE_ENV=\"production\") || exists(r\"**/*.rs\") -->x<!-- endif -->",
                 checks: vec![Check::BlocksLen(1), Check::HasText("x")],
             },
+            Case {
+                name: "if else",
+                input: "<!-- if env(CI) -->A<!-- else -->B<!-- endif -->",
+                checks: vec![
+                    Check::BlocksLen(1),
+                    Check::IfShape(1, true),
+                    Check::HasText("A"),
+                    Check::HasText("B"),
+                ],
+            },
+            Case {
+                name: "if elif else",
+                input: "<!-- if env(A) -->A<!-- elif env(B) -->B<!-- elif env(C) -->C<!--else-->D<!-- endif -->",
+                checks: vec![
+                    Check::BlocksLen(1),
+                    Check::IfShape(3, true),
+                    Check::HasMatcher(Matcher::EnvExists("C".into())),
+                    Check::HasText("D"),
+                ],
+            },
+            Case {
+                name: "keywords need a word boundary",
+                input: "<!-- elsewhere -->",
+                checks: vec![Check::BlocksLen(1), Check::HasText("elsewhere")],
+            },
+            Case {
+                name: "include directive",
+                input: "A\n<!-- include \"rust.md\" -->\nB\n",
+                checks: vec![Check::BlocksLen(3), Check::HasText("B")],
+            },
+            Case {
+                name: "interpolation",
+                input: "Crate {{ package.name }} in {{project.name}}.",
+                checks: vec![
+                    Check::BlocksLen(5),
+                    Check::HasText("Crate "),
+                    Check::HasText("."),
+                ],
+            },
+            Case {
+                name: "escaped interpolation",
+                input: "Handlebars: \\{{ name }}",
+                checks: vec![
+                    Check::BlocksLen(1),
+                    Check::HasText("Handlebars: {{ name }}"),
+                ],
+            },
+            Case {
+                name: "section",
+                input: "<!-- section testing -->\nRun tests\n<!-- if env(CI) -->CI<!-- endif --><!-- endsection -->",
+                checks: vec![
+                    Check::BlocksLen(1),
+                    Check::HasText("Run tests"),
+                    Check::HasMatcher(Matcher::EnvExists("CI".into())),
+                ],
+            },
+            Case {
+                name: "section with mode",
+                input: "<!-- section testing append -->extra<!-- endsection -->",
+                checks: vec![Check::BlocksLen(1), Check::HasText("extra")],
+            },
+            Case {
+                name: "extends with blocks",
+                input: "<!-- extends \"~/base.md\" -->\n<!-- block intro -->Hi <!-- super --><!-- endblock -->",
+                checks: vec![Check::BlocksLen(2), Check::HasText("Hi ")],
+            },
+            Case {
+                name: "for over files",
+                input: "<!-- for c in files(\"crates/*/Cargo.toml\") -->\n- {{ c.dir }}<!-- if exists(\"{{ c.dir }}/src/main.rs\") --> (bin)<!-- endif -->\n<!-- endfor -->",
+                checks: vec![
+                    Check::BlocksLen(1),
+                    Check::HasText("- "),
+                    Check::HasMatcher(Matcher::Exists("{{ c.dir }}/src/main.rs".into())),
+                ],
+            },
+            Case {
+                name: "for over languages",
+                input: "<!-- for lang in languages -->{{ lang }}<!-- endfor -->",
+                checks: vec![Check::BlocksLen(1)],
+            },
+            Case {
+                name: "definitions",
+                input: "<!-- define rust = exists(Cargo.toml) -->\n<!-- define local = rust && !env(CI) -->\n<!-- if local -->L<!-- endif -->",
+                checks: vec![
+                    Check::BlocksLen(5),
+                    Check::HasMatcher(Matcher::Exists("Cargo.toml".into())),
+                    Check::HasMatcher(Matcher::EnvExists("CI".into())),
+                ],
+            },
             Case {
                 name: "note comments are preserved in template",
                 input: "<!-- note:\nInternal only\n-->\nVisible text\n",
@@ -526,7 +2129,8 @@ mod tests {
         ];
 
         for c in cases {
-            let tpl = parse_template(c.input).unwrap_or_else(|e| panic!("{}: {e}", c.name));
+            let tpl = parse(c.input, ParseOptions::default())
+                .unwrap_or_else(|e| panic!("{}: {e}", c.name));
             validate(&tpl, &c.checks);
         }
     }
@@ -549,12 +2153,501 @@ mod tests {
                 input: "<!-- if env(CI) -->",
                 contains: "unclosed",
             },
+            ErrCase {
+                name: "else outside if",
+                input: "<!-- else -->",
+                contains: "stray 'else'",
+            },
+            ErrCase {
+                name: "elif outside if",
+                input: "<!-- elif env(CI) -->",
+                contains: "stray 'elif'",
+            },
+            ErrCase {
+                name: "duplicate else",
+                input: "<!-- if env(CI) -->a<!-- else -->b<!-- else -->c<!-- endif -->",
+                contains: "duplicate 'else'",
+            },
+            ErrCase {
+                name: "elif after else",
+                input: "<!-- if env(CI) -->a<!-- else -->b<!-- elif env(X) -->c<!-- endif -->",
+                contains: "'elif' after 'else'",
+            },
+            ErrCase {
+                name: "unknown fact",
+                input: "Hello {{ nope }}",
+                contains: "unknown fact: nope",
+            },
+            ErrCase {
+                name: "unterminated interpolation",
+                input: "Hello {{ package.name",
+                contains: "unterminated interpolation",
+            },
+            ErrCase {
+                name: "nested section",
+                input: "<!-- section a --><!-- section b --><!-- endsection --><!-- endsection -->",
+                contains: "cannot be nested",
+            },
+            ErrCase {
+                name: "unknown section mode",
+                input: "<!-- section a sideways --><!-- endsection -->",
+                contains: "unknown section mode",
+            },
+            ErrCase {
+                name: "section without name",
+                input: "<!-- section --><!-- endsection -->",
+                contains: "requires a name",
+            },
+            ErrCase {
+                name: "include with trailing content",
+                input: "<!-- include \"a.md\" b.md -->",
+                contains: "after include path",
+            },
+            ErrCase {
+                name: "unclosed section",
+                input: "<!-- section a -->body",
+                contains: "unclosed 'section'",
+            },
+            ErrCase {
+                name: "endif closing a section",
+                input: "<!-- section a -->body<!-- endif -->",
+                contains: "stray 'endif'",
+            },
+            ErrCase {
+                name: "duplicate block",
+                input: "<!-- block a -->x<!-- endblock --><!-- block a -->y<!-- endblock -->",
+                contains: "duplicate block 'a'",
+            },
+            ErrCase {
+                name: "super outside block",
+                input: "<!-- super -->",
+                contains: "outside of a block",
+            },
+            ErrCase {
+                name: "nested extends",
+                input: "<!-- if env(CI) --><!-- extends \"b.md\" --><!-- endif -->",
+                contains: "top level",
+            },
+            ErrCase {
+                name: "duplicate extends",
+                input: "<!-- extends \"a.md\" --><!-- extends \"b.md\" -->",
+                contains: "duplicate 'extends'",
+            },
+            ErrCase {
+                name: "unknown loop source",
+                input: "<!-- for x in crates --><!-- endfor -->",
+                contains: "expected 'files(GLOB)' or 'languages'",
+            },
+            ErrCase {
+                name: "for without in",
+                input: "<!-- for x files(\"*\") --><!-- endfor -->",
+                contains: "expected 'in'",
+            },
+            ErrCase {
+                name: "stray endfor",
+                input: "<!-- endfor -->",
+                contains: "stray 'endfor'",
+            },
+            ErrCase {
+                name: "loop variable out of scope",
+                input: "<!-- for c in languages --><!-- endfor -->{{ c }}",
+                contains: "unknown fact: c",
+            },
+            ErrCase {
+                name: "unknown variable in matcher",
+                input: "<!-- if exists(\"{{ c.dir }}/x\") -->x<!-- endif -->",
+                contains: "unknown fact: c.dir",
+            },
+            ErrCase {
+                name: "bad attribute on string variable",
+                input: "<!-- for l in languages -->{{ l.dir }}<!-- endfor -->",
+                contains: "unknown attribute 'dir'",
+            },
+            ErrCase {
+                name: "unknown language in an unevaluated operand",
+                input: "<!-- if env(CI) || lang(rsut) -->x<!-- endif -->",
+                contains: "unknown language: rsut",
+            },
+            ErrCase {
+                name: "invalid glob in elif",
+                input: "<!-- if env(CI) -->a<!-- elif exists(\"{foo\") -->b<!-- endif -->",
+                contains: "invalid exists() pattern",
+            },
+            ErrCase {
+                name: "invalid contains regex",
+                input: "<!-- if contains(\"*.rs\", \"fn (\", regex) -->x<!-- endif -->",
+                contains: "invalid contains() regex",
+            },
+            ErrCase {
+                name: "contains without a pattern",
+                input: "<!-- if contains(\"*.rs\") -->x<!-- endif -->",
+                contains: "contains requires a glob and a pattern",
+            },
+            ErrCase {
+                name: "unknown contains flag",
+                input: "<!-- if contains(\"*.rs\", x, re) -->x<!-- endif -->",
+                contains: "expected 'regex'",
+            },
+            ErrCase {
+                name: "unknown ecosystem",
+                input: "<!-- if dep(maven:junit) -->x<!-- endif -->",
+                contains: "unknown ecosystem 'maven'",
+            },
+            ErrCase {
+                name: "dep without a name",
+                input: "<!-- if dep(cargo:) -->x<!-- endif -->",
+                contains: "dep requires a package name",
+            },
+            ErrCase {
+                name: "unknown dep flag",
+                input: "<!-- if dep(react, build) -->x<!-- endif -->",
+                contains: "expected 'dev'",
+            },
+            ErrCase {
+                name: "unknown edition",
+                input: "<!-- if cargo_edition(2023) -->x<!-- endif -->",
+                contains: "unknown Rust edition: 2023",
+            },
+            ErrCase {
+                name: "cargo_workspace with an argument",
+                input: "<!-- if cargo_workspace(x) -->x<!-- endif -->",
+                contains: "cargo_workspace takes no arguments",
+            },
+            ErrCase {
+                name: "invalid loop glob",
+                input: "<!-- for f in files(\"[a\") -->x<!-- endfor -->",
+                contains: "invalid files() pattern",
+            },
+            ErrCase {
+                name: "undefined condition",
+                input: "<!-- if rust_local && env(CI) -->x<!-- endif -->",
+                contains: "undefined condition 'rust_local'",
+            },
+            ErrCase {
+                name: "use before definition",
+                input: "<!-- if a -->x<!-- endif --><!-- define a = env(A) -->",
+                contains: "undefined condition 'a'",
+            },
+            ErrCase {
+                name: "recursive definition",
+                input: "<!-- define a = env(A) || !a -->",
+                contains: "recursive definition of 'a'",
+            },
+            ErrCase {
+                name: "duplicate definition",
+                input: "<!-- define a = env(A) --><!-- define a = env(B) -->",
+                contains: "duplicate definition 'a'",
+            },
+            ErrCase {
+                name: "defining a matcher name",
+                input: "<!-- define lang = env(A) -->",
+                contains: "'lang' is a matcher",
+            },
+            ErrCase {
+                name: "loop variable in definition",
+                input: "<!-- for f in files(\"*\") --><!-- define a = exists(\"{{ f }}\") --><!-- endfor -->",
+                contains: "unknown fact: f",
+            },
+            ErrCase {
+                name: "unclosed raw",
+                input: "<!-- raw -->{{ x }}<!-- if env(A) -->",
+                contains: "unclosed 'raw' block",
+            },
+            ErrCase {
+                name: "stray endraw",
+                input: "x<!-- endraw -->",
+                contains: "stray 'endraw'",
+            },
+            ErrCase {
+                name: "content after endraw",
+                input: "<!-- raw -->x<!-- endraw now -->",
+                contains: "unexpected content after 'endraw'",
+            },
+            ErrCase {
+                name: "once without key",
+                input: "<!-- once -->x<!-- endonce -->",
+                contains: "once requires a key",
+            },
+            ErrCase {
+                name: "unclosed once",
+                input: "<!-- once a -->x",
+                contains: "unclosed 'once' block",
+            },
+            ErrCase {
+                name: "stray endonce",
+                input: "<!-- if env(A) --><!-- endonce --><!-- endif -->",
+                contains: "stray 'endonce'",
+            },
+            ErrCase {
+                name: "content after else",
+                input: "<!-- if env(CI) -->a<!-- else env(X) -->b<!-- endif -->",
+                contains: "unexpected content after 'else'",
+            },
+        ];
+        for c in cases {
+            let err = parse(c.input, ParseOptions::default()).unwrap_err();
+            let msgs = messages(&err);
+            assert!(
+                msgs.iter().any(|m| m.contains(c.contains)),
+                "{}: {msgs:?}",
+                c.name
+            );
+        }
+    }
+
+    /// Messages of the located errors in `err`, in report order.
+    fn messages(err: &Error) -> Vec<String> {
+        match err {
+            Error::Located(d) => vec![d.message.clone()],
+            Error::Multiple(errs) => errs.iter().flat_map(messages).collect(),
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn misplaced_else_and_elif_are_reported_at_the_tag() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            message: &'static str,
+            /// The tag the error points at, by its last occurrence.
+            at: &'static str,
+        }
+        let cases = vec![
+            Case {
+                name: "duplicate else",
+                input: "<!-- if env(A) -->a<!-- else -->b<!-- else -->c<!-- endif -->",
+                message: "duplicate 'else'",
+                at: "<!-- else -->",
+            },
+            Case {
+                name: "elif after else",
+                input: "<!-- if env(A) -->a<!-- else -->b<!-- elif env(B) -->c<!-- endif -->",
+                message: "'elif' after 'else'",
+                at: "<!-- elif env(B) -->",
+            },
+            Case {
+                name: "else directly in a section",
+                input: "<!-- if env(A) --><!-- section S -->a<!-- else -->b<!-- endsection --><!-- endif -->",
+                message: "stray 'else'",
+                at: "<!-- else -->",
+            },
+            Case {
+                name: "elif after endif",
+                input: "<!-- if env(A) -->a<!-- endif -->\n<!-- elif env(B) -->b",
+                message: "stray 'elif'",
+                at: "<!-- elif env(B) -->",
+            },
+        ];
+        for c in cases {
+            let err = parse(c.input, ParseOptions::default()).unwrap_err();
+            let msgs = messages(&err);
+            let Some(Error::Located(d)) = (match &err {
+                Error::Multiple(errors) => errors.first(),
+                e => Some(e),
+            }) else {
+                panic!("{}: unlocated error {err:?}", c.name);
+            };
+            assert!(d.message.contains(c.message), "{}: {msgs:?}", c.name);
+            let start = c.input.rfind(c.at).unwrap();
+            assert_eq!(d.span, Span::new(start, start + c.at.len()), "{}", c.name);
+        }
+    }
+
+    #[test]
+    fn invalid_matcher_arguments_are_located_in_every_branch() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            message: &'static str,
+            at: &'static str,
+        }
+        let cases = vec![
+            Case {
+                name: "unknown language in elif",
+                input: "<!-- if env(A) -->a<!-- elif lang(rsut) -->b<!-- endif -->",
+                message: "unknown language: rsut",
+                at: "lang(rsut)",
+            },
+            Case {
+                name: "bad glob in elif",
+                input: "<!-- if env(A) -->a<!-- elif exists(\"[a\") -->b<!-- endif -->",
+                message: "invalid exists() pattern",
+                at: "exists(\"[a\")",
+            },
+            Case {
+                name: "unknown language in define",
+                input: "<!-- define py = lang(pyhton) -->",
+                message: "unknown language: pyhton",
+                at: "lang(pyhton)",
+            },
+            Case {
+                name: "bad glob in define",
+                input: "<!-- define docs = exists(\"docs/{a\") -->",
+                message: "invalid exists() pattern",
+                at: "exists(\"docs/{a\")",
+            },
+            Case {
+                name: "short-circuited operand in a nested else",
+                input: "<!-- if env(A) -->a<!-- else --><!-- if env(B) || lang(rsut) -->b<!-- endif --><!-- endif -->",
+                message: "unknown language: rsut",
+                at: "lang(rsut)",
+            },
+        ];
+        for c in cases {
+            let err = parse(c.input, ParseOptions::default()).unwrap_err();
+            let Error::Located(d) = &err else {
+                panic!("{}: expected one located error, got {err:?}", c.name);
+            };
+            assert!(d.message.contains(c.message), "{}: {}", c.name, d.message);
+            assert_eq!(&c.input[d.span.start..d.span.end], c.at, "{}", c.name);
+        }
+    }
+
+    #[test]
+    fn undefined_and_recursive_conditions_are_located() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            message: &'static str,
+            /// Byte offset and text of the name the error points at.
+            at: (usize, &'static str),
+        }
+        let cases = vec![
+            Case {
+                name: "undefined in elif",
+                input: "<!-- if env(A) -->a<!-- elif rust_local -->b<!-- endif -->",
+                message: "undefined condition 'rust_local'",
+                at: (29, "rust_local"),
+            },
+            Case {
+                name: "undefined in another definition",
+                input: "<!-- define ci_rust = rust && env(CI) -->",
+                message: "undefined condition 'rust'",
+                at: (22, "rust"),
+            },
+            Case {
+                name: "used before its definition",
+                input: "<!-- if later -->x<!-- endif --><!-- define later = env(A) -->",
+                message: "undefined condition 'later'",
+                at: (8, "later"),
+            },
+            Case {
+                name: "defined in terms of itself",
+                input: "<!-- define local = env(A) || !local -->",
+                message: "recursive definition of 'local'",
+                at: (31, "local"),
+            },
+            Case {
+                name: "later uses of a recursive definition",
+                input: "<!-- define a = !a -->\n<!-- if a -->x<!-- endif -->",
+                message: "recursive definition of 'a'",
+                at: (17, "a"),
+            },
+        ];
+        for c in cases {
+            let err = parse(c.input, ParseOptions::default()).unwrap_err();
+            let Error::Located(d) = &err else {
+                panic!("{}: expected one located error, got {err:?}", c.name);
+            };
+            assert!(d.message.contains(c.message), "{}: {}", c.name, d.message);
+            let (start, text) = c.at;
+            assert_eq!(d.span, Span::new(start, start + text.len()), "{}", c.name);
+            assert_eq!(&c.input[d.span.start..d.span.end], text, "{}", c.name);
+        }
+    }
+
+    #[test]
+    fn typos_in_openers_are_reported_at_the_opener() {
+        let cases = [
+            ("<!-- include \"frag.md -->", "unterminated string"),
+            ("<!-- define 1x = env(A) -->", "invalid definition name: 1x"),
+            (
+                "<!-- extends base.md extra -->",
+                "unexpected content after extends path",
+            ),
+            (
+                "<!-- for f in file(\"x\") -->{{ f }}<!-- endfor -->",
+                "expected 'files(GLOB)' or 'languages'",
+            ),
+            (
+                "<!-- section Testing Stuff -->x<!-- endsection -->",
+                "unknown section mode 'Stuff'",
+            ),
+            (
+                "<!-- raw x -->y<!-- endraw -->",
+                "unexpected content after 'raw'",
+            ),
+            (
+                "<!-- block my block -->x<!-- endblock -->",
+                "unexpected content after block 'my'",
+            ),
+        ];
+        for (input, want) in cases {
+            let opener_end = input.find("-->").unwrap() + 3;
+            let err = parse(input, ParseOptions::default()).unwrap_err();
+            let Error::Located(d) = &err else {
+                panic!("{input}: expected one located error, got {err:?}");
+            };
+            assert!(d.message.contains(want), "{input}: {}", d.message);
+            assert!(d.span.end <= opener_end, "{input}: {:?}", d.span);
+        }
+    }
+
+    #[test]
+    fn recovers_and_reports_every_error() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            expect: &'static [&'static str],
+        }
+        let cases = vec![
+            Case {
+                name: "bad tags and text are skipped",
+                input: "<!-- endif -->\n{{ nope }}\n<!-- section -->x<!-- endsection -->\n<!-- if exists(a) ) --><!-- endif -->",
+                expect: &[
+                    "stray 'endif'",
+                    "unknown fact: nope",
+                    "section requires a name",
+                    "trailing characters in expression",
+                ],
+            },
+            Case {
+                name: "rejected opener still matches its closer",
+                input: "<!-- if bogus(x) -->a<!-- elif env(A) -->b<!-- else -->c<!-- endif --><!-- endfor -->",
+                expect: &["expected matcher or '('", "stray 'endfor'"],
+            },
+            Case {
+                name: "every bad operand in an expression",
+                input: "<!-- if foo(a) && exists(\"x && y\") || bar(b) -->x<!-- endif -->",
+                expect: &["expected matcher or '('", "expected matcher or '('"],
+            },
+            Case {
+                name: "every invalid matcher argument",
+                input: "<!-- if lang(rsut) || !exists(\"{a\") && lang(rust) -->x<!-- endif -->",
+                expect: &["unknown language: rsut", "invalid exists() pattern"],
+            },
+            Case {
+                name: "nested raw blocks",
+                input: "<!-- raw --><!-- raw -->x<!-- endraw --><!-- endraw -->",
+                expect: &["'raw' blocks cannot be nested", "stray 'endraw'"],
+            },
+            Case {
+                name: "unclosed blocks are sorted by position",
+                input: "<!-- for f in languages --><!-- if env(A) -->{{ f.dir }}",
+                expect: &[
+                    "unclosed 'for' block",
+                    "unclosed 'if' block",
+                    "unknown attribute 'dir'",
+                ],
+            },
         ];
         for c in cases {
-            let err = parse_template(c.input).unwrap_err();
-            match err {
-                Error::Template(msg) => assert!(msg.contains(c.contains), "{}: {msg}", c.name),
-                other => panic!("{}: unexpected error {other:?}", c.name),
+            let err = parse(c.input, ParseOptions::default()).unwrap_err();
+            let msgs = messages(&err);
+            assert_eq!(msgs.len(), c.expect.len(), "{}: {msgs:?}", c.name);
+            for (m, want) in msgs.iter().zip(c.expect) {
+                assert!(m.contains(want), "{}: {msgs:?}", c.name);
             }
         }
     }
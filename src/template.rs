@@ -1,48 +1,473 @@
-use crate::error::Error;
-use crate::expr::Expr;
+use crate::context::{Binding, Context, Scope, VarKind};
+use crate::error::{Error, Source, Span};
+use crate::expr::{Definition, Expr, matching_files};
+use crate::facts::{Fact, detected_languages, interpolate};
+use crate::frontmatter::FrontMatter;
+#[cfg(test)]
+use crate::parse::ParseOptions;
+#[cfg(test)]
 use std::path::Path;
+use std::sync::Arc;
 
-/// A top‑level template unit: literal text or a conditional block.
+/// A top‑level template unit: literal text, an interpolated fact, a
+/// conditional block, an included fragment, a named section, or an
+/// inheritance block.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Block {
     Text(String),
-    If { cond: Expr, body: Vec<Block> },
+    /// `{{ fact }}` in template text.
+    Interpolate {
+        fact: Fact,
+        /// The `{{ … }}`, for errors resolving `fact`.
+        span: Span,
+    },
+    /// `<!-- # note -->`: a template‑private comment that renders to nothing.
+    Comment {
+        text: String,
+        trim: Trim,
+    },
+    /// `<!-- include "path" -->`; `template` is filled in by the loader.
+    Include {
+        path: String,
+        template: Option<Template>,
+        trim: Trim,
+        /// The tag, for errors loading `path`.
+        span: Span,
+    },
+    /// `if`/`elif` branches in source order, plus an optional `else` body.
+    If {
+        branches: Vec<Branch>,
+        else_body: Option<Vec<Block>>,
+        /// Markers on the `else` tag; default when there is no `else`.
+        else_trim: Trim,
+        end_trim: Trim,
+    },
+    /// `<!-- section NAME [MODE] --> … <!-- endsection -->`.
+    Section {
+        name: String,
+        mode: SectionMode,
+        body: Vec<Block>,
+        open: Trim,
+        close: Trim,
+    },
+    /// `<!-- block NAME --> … <!-- endblock -->`: a slot that templates
+    /// extending this one may override.
+    Slot {
+        name: String,
+        body: Vec<Block>,
+        open: Trim,
+        close: Trim,
+        /// The opening tag, for errors about the parent template.
+        span: Span,
+    },
+    /// `<!-- define NAME = EXPR -->`: names a condition for later
+    /// expressions. Renders to nothing.
+    Define {
+        def: Arc<Definition>,
+        trim: Trim,
+    },
+    /// `<!-- once "KEY" --> … <!-- endonce -->`: rendered only the first
+    /// time `KEY` is seen in a run, whichever template it is in.
+    Once {
+        key: String,
+        body: Vec<Block>,
+        open: Trim,
+        close: Trim,
+    },
+    /// `<!-- raw --> … <!-- endraw -->`: text copied to the output exactly,
+    /// with no tags or interpolations.
+    Raw {
+        text: String,
+        open: Trim,
+        close: Trim,
+    },
+    /// `<!-- super -->`: the parent template's content for the enclosing block.
+    Super(Trim),
+    /// `<!-- for VAR in SOURCE --> … <!-- endfor -->`.
+    For {
+        var: String,
+        source: LoopSource,
+        body: Vec<Block>,
+        open: Trim,
+        close: Trim,
+        /// The opening tag, for errors evaluating `source`.
+        span: Span,
+    },
+}
+
+/// Whitespace‑control markers on a control tag. `<!---` strips the newline
+/// and indentation before the tag; `--->` strips trailing spaces and the
+/// newline after it. The parser applies them; they are kept on the AST so
+/// the template can be printed back unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Trim {
+    pub left: bool,
+    pub right: bool,
+}
+
+/// What a `for` block iterates over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopSource {
+    /// `files(GLOB)`: matching non‑ignored files, sorted by path.
+    Files(String),
+    /// `languages`: detected programming languages, sorted by name.
+    Languages,
+}
+
+impl LoopSource {
+    /// The kind of value the loop variable is bound to.
+    pub fn kind(&self) -> VarKind {
+        match self {
+            LoopSource::Files(_) => VarKind::File,
+            LoopSource::Languages => VarKind::Str,
+        }
+    }
+}
+
+/// How a project‑local section combines with a shared section of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionMode {
+    /// The local body replaces the shared body (the default).
+    Replace,
+    /// The local body is emitted after the shared body.
+    Append,
+    /// The local body is emitted before the shared body.
+    Prepend,
+}
+
+/// A piece of rendered output: plain text or the rendered body of a section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Text(String),
+    Section {
+        name: String,
+        mode: SectionMode,
+        text: String,
+    },
+}
+
+/// One `if` or `elif` arm of a conditional block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub cond: Expr,
+    pub body: Vec<Block>,
+    pub trim: Trim,
 }
 
-/// Parsed representation of a template: a linear sequence of blocks.
+/// Parsed representation of a template: a linear sequence of blocks, plus
+/// the template it extends, if any.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Template {
     pub blocks: Vec<Block>,
+    pub extends: Option<Extends>,
+    /// Metadata from the front matter, which is not part of `blocks`.
+    pub front_matter: Option<FrontMatter>,
+    /// The text the template was parsed from, which spans point into.
+    pub source: Arc<Source>,
+}
+
+/// `<!-- extends "path" -->`; `parent` is filled in by the loader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extends {
+    pub path: String,
+    pub parent: Option<Box<Template>>,
+    pub trim: Trim,
+    /// The tag, for errors loading `path`.
+    pub span: Span,
 }
 
 impl Template {
+    #[cfg(test)]
     pub fn parse(input: &str) -> Result<Self, Error> {
-        crate::parse::parse_template(input)
+        Self::parse_with(input, ParseOptions::default())
     }
 
-    /// Render this template against the given project root.
-    ///
-    /// Appends all literal text blocks and the bodies of conditional blocks
-    /// whose expressions evaluate to true.
+    #[cfg(test)]
+    pub fn parse_with(input: &str, opts: ParseOptions) -> Result<Self, Error> {
+        let source = Source {
+            path: None,
+            text: input.to_string(),
+        };
+        let (tpl, errors) = crate::parse::parse_template(&Arc::new(source), opts);
+        Error::from_all(errors).map(|()| tpl)
+    }
+
+    /// Render this template against the given project root with a default
+    /// context.
+    #[cfg(test)]
     pub fn render(&self, root: &Path) -> Result<String, Error> {
-        let mut out = String::new();
-        render_blocks(&self.blocks, root, &mut out)?;
+        Ok(flatten(&self.render_parts(&Context::new(root))?))
+    }
+
+    /// Render this template against the project described by `ctx`.
+    ///
+    /// Emits all literal text blocks and interpolated facts and, for each
+    /// conditional block, the body of the first branch whose expression
+    /// evaluates to true (or the `else` body when none do). Section bodies are
+    /// kept as separate parts so they can be combined with another template's
+    /// sections.
+    ///
+    /// A template that extends another renders its root ancestor, with each
+    /// `block` taken from the most‑derived template that defines it.
+    pub fn render_parts(&self, ctx: &Context) -> Result<Vec<Part>, Error> {
+        let mut levels = vec![self];
+        let mut base = self;
+        while let Some(ext) = &base.extends {
+            base = ext
+                .parent
+                .as_deref()
+                .ok_or_else(|| Error::Template(format!("unresolved extends: {}", ext.path)))?;
+            levels.push(base);
+        }
+        let mut patterns = Vec::new();
+        for level in &levels {
+            exists_patterns(&level.blocks, &mut patterns);
+        }
+        if !patterns.is_empty() {
+            ctx.files().prime_exists(patterns);
+        }
+        let renderer = Renderer { ctx, levels };
+        let mut out = Vec::new();
+        renderer
+            .blocks(&base.blocks, None, &Scope::default(), &mut out)
+            .map_err(|e| e.in_source(&base.source).rendering())?;
         Ok(out)
     }
 }
 
-fn render_blocks(blocks: &[Block], root: &Path, out: &mut String) -> Result<(), Error> {
+/// Add the `exists` patterns of every condition in `blocks` and in the
+/// templates they include to `out`, so they can be evaluated together.
+fn exists_patterns<'a>(blocks: &'a [Block], out: &mut Vec<&'a str>) {
+    for block in blocks {
+        match block {
+            Block::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for b in branches {
+                    b.cond.exists_patterns(out);
+                    exists_patterns(&b.body, out);
+                }
+                exists_patterns(else_body.as_deref().unwrap_or_default(), out);
+            }
+            Block::Define { def, .. } => def.expr.exists_patterns(out),
+            Block::Include {
+                template: Some(t), ..
+            } => exists_patterns(&t.blocks, out),
+            Block::Section { body, .. }
+            | Block::Slot { body, .. }
+            | Block::Once { body, .. }
+            | Block::For { body, .. } => exists_patterns(body, out),
+            Block::Text(_)
+            | Block::Interpolate { .. }
+            | Block::Comment { .. }
+            | Block::Include { .. }
+            | Block::Raw { .. }
+            | Block::Super(_) => {}
+        }
+    }
+}
+
+/// Concatenate rendered parts, emitting section bodies in place.
+pub fn flatten(parts: &[Part]) -> String {
+    let mut out = String::new();
+    for p in parts {
+        match p {
+            Part::Text(s) | Part::Section { text: s, .. } => out.push_str(s),
+        }
+    }
+    out
+}
+
+fn push_text(out: &mut Vec<Part>, s: &str) {
+    if let Some(Part::Text(last)) = out.last_mut() {
+        last.push_str(s);
+    } else {
+        out.push(Part::Text(s.to_string()));
+    }
+}
+
+/// Find the body of block `name` anywhere within `tpl`, along with the
+/// template (`tpl` or an included one) whose source it was parsed from.
+pub fn find_slot<'a>(tpl: &'a Template, name: &str) -> Option<(&'a Template, &'a [Block])> {
+    slot_in(tpl, &tpl.blocks, name)
+}
+
+fn slot_in<'a>(
+    tpl: &'a Template,
+    blocks: &'a [Block],
+    name: &str,
+) -> Option<(&'a Template, &'a [Block])> {
     for b in blocks {
-        match b {
-            Block::Text(s) => out.push_str(s),
-            Block::If { cond, body } => {
-                if cond.is_match(root)? {
-                    render_blocks(body, root, out)?;
+        let found = match b {
+            Block::Text(_)
+            | Block::Interpolate { .. }
+            | Block::Comment { .. }
+            | Block::Define { .. }
+            | Block::Raw { .. }
+            | Block::Super(_) => None,
+            Block::For { body, .. } | Block::Once { body, .. } => slot_in(tpl, body, name),
+            Block::Slot { name: n, body, .. } if n == name => return Some((tpl, body)),
+            Block::Slot { body, .. } | Block::Section { body, .. } => slot_in(tpl, body, name),
+            Block::Include { template, .. } => template.as_ref().and_then(|t| find_slot(t, name)),
+            Block::If {
+                branches,
+                else_body,
+                ..
+            } => branches
+                .iter()
+                .find_map(|br| slot_in(tpl, &br.body, name))
+                .or_else(|| else_body.as_deref().and_then(|b| slot_in(tpl, b, name))),
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// Evaluate `cond` for the project in `ctx`, first substituting facts and
+/// the loop variables in `scope` into its matcher arguments.
+pub fn eval_condition(cond: &Expr, ctx: &Context, scope: &Scope) -> Result<bool, Error> {
+    if cond.has_interpolation() {
+        let bound = cond.map_args(&mut |s| interpolate(s, ctx, scope))?;
+        bound.is_match(ctx)
+    } else {
+        cond.is_match(ctx)
+    }
+}
+
+struct Renderer<'a> {
+    ctx: &'a Context,
+    /// Inheritance chain, most‑derived template first.
+    levels: Vec<&'a Template>,
+}
+
+impl Renderer<'_> {
+    /// Definitions of block `name` and the templates they come from,
+    /// most‑derived first.
+    fn slot_defs(&self, name: &str) -> Vec<(&Template, &[Block])> {
+        self.levels
+            .iter()
+            .filter_map(|t| find_slot(t, name))
+            .collect()
+    }
+
+    fn eval(&self, cond: &Expr, scope: &Scope) -> Result<bool, Error> {
+        eval_condition(cond, self.ctx, scope)
+    }
+
+    /// Render `blocks`; `sup` is the enclosing block name and the index of
+    /// the definition being rendered, which `super` steps past, and `scope`
+    /// holds the loop variables in effect.
+    fn blocks(
+        &self,
+        blocks: &[Block],
+        sup: Option<(&str, usize)>,
+        scope: &Scope,
+        out: &mut Vec<Part>,
+    ) -> Result<(), Error> {
+        for b in blocks {
+            match b {
+                Block::Text(s) | Block::Raw { text: s, .. } => push_text(out, s),
+                Block::Interpolate { fact, span } => {
+                    let text = fact.resolve(self.ctx, scope).map_err(|e| e.or_at(*span))?;
+                    push_text(out, &text);
+                }
+                Block::Comment { .. } | Block::Define { .. } => {}
+                Block::Include { path, template, .. } => match template {
+                    Some(tpl) => self
+                        .blocks(&tpl.blocks, sup, scope, out)
+                        .map_err(|e| e.in_source(&tpl.source))?,
+                    None => {
+                        return Err(Error::Template(format!("unresolved include: {path}")));
+                    }
+                },
+                Block::If {
+                    branches,
+                    else_body,
+                    ..
+                } => {
+                    let mut taken = false;
+                    for br in branches {
+                        if self.eval(&br.cond, scope)? {
+                            self.blocks(&br.body, sup, scope, out)?;
+                            taken = true;
+                            break;
+                        }
+                    }
+                    if !taken && let Some(body) = else_body {
+                        self.blocks(body, sup, scope, out)?;
+                    }
+                }
+                Block::Once { key, body, .. } => {
+                    let key = interpolate(key, self.ctx, scope)?;
+                    if self.ctx.once.lock().unwrap().insert(key) {
+                        self.blocks(body, sup, scope, out)?;
+                    }
+                }
+                Block::Section {
+                    name, mode, body, ..
+                } => {
+                    let mut inner = Vec::new();
+                    self.blocks(body, sup, scope, &mut inner)?;
+                    out.push(Part::Section {
+                        name: name.clone(),
+                        mode: *mode,
+                        text: flatten(&inner),
+                    });
+                }
+                Block::Slot { name, body, .. } => {
+                    let defs = self.slot_defs(name);
+                    match defs.first() {
+                        Some((tpl, first)) => self
+                            .blocks(first, Some((name, 0)), scope, out)
+                            .map_err(|e| e.in_source(&tpl.source))?,
+                        None => self.blocks(body, Some((name, 0)), scope, out)?,
+                    }
+                }
+                Block::Super(_) => {
+                    let (name, idx) =
+                        sup.ok_or_else(|| Error::Template("'super' outside of a block".into()))?;
+                    let defs = self.slot_defs(name);
+                    let (tpl, parent) = defs.get(idx + 1).ok_or_else(|| {
+                        Error::Template(format!(
+                            "'super' in block '{name}' has no parent definition"
+                        ))
+                    })?;
+                    self.blocks(parent, Some((name, idx + 1)), scope, out)
+                        .map_err(|e| e.in_source(&tpl.source))?;
+                }
+                Block::For {
+                    var,
+                    source,
+                    body,
+                    span,
+                    ..
+                } => {
+                    let items = match source {
+                        LoopSource::Files(pattern) => {
+                            let pattern = interpolate(pattern, self.ctx, scope)?;
+                            matching_files(self.ctx, &pattern)
+                                .map_err(|e| e.or_at(*span))?
+                                .into_iter()
+                                .map(Binding::File)
+                                .collect::<Vec<_>>()
+                        }
+                        LoopSource::Languages => detected_languages(self.ctx)
+                            .into_iter()
+                            .map(Binding::Str)
+                            .collect(),
+                    };
+                    for item in items {
+                        self.blocks(body, sup, &scope.with(var, item), out)?;
+                    }
                 }
             }
         }
+        Ok(())
     }
-    Ok(())
 }
 
 #[cfg(test)]
@@ -77,15 +502,329 @@ mod tests {
         assert!(out2.contains("Matched"));
     }
 
+    #[test]
+    fn render_picks_first_true_branch() {
+        let src = "<!-- if exists(\"a.txt\") -->A<!-- elif exists(\"b.txt\") -->B<!-- else -->C<!-- endif -->";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), "C");
+        fs::File::create(td.path().join("b.txt")).unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), "B");
+        // Earlier branches win even when later ones also match
+        fs::File::create(td.path().join("a.txt")).unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), "A");
+    }
+
+    #[test]
+    fn render_evaluates_definitions_once() {
+        let src = "<!-- define rust = exists(\"Cargo.toml\") && !exists(\"rust-toolchain.toml\") -->\
+                   <!-- if rust -->A<!-- endif --><!-- if !rust -->B<!-- endif --><!-- if rust -->C<!-- endif -->";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::File::create(td.path().join("Cargo.toml")).unwrap();
+        let ctx = Context::new(td.path());
+        assert_eq!(flatten(&tpl.render_parts(&ctx).unwrap()), "AC");
+        assert_eq!(ctx.defined.lock().unwrap().len(), 1);
+        // The cached result stands for the rest of the run.
+        fs::File::create(td.path().join("rust-toolchain.toml")).unwrap();
+        assert_eq!(flatten(&tpl.render_parts(&ctx).unwrap()), "AC");
+        assert_eq!(tpl.render(td.path()).unwrap(), "B");
+    }
+
+    #[test]
+    fn render_walks_the_project_once() {
+        let src = "<!-- if exists(\"a.rs\") -->a<!-- endif -->\
+                   <!-- for f in files(\"*.rs\") --><!-- if exists(\"{{ f.stem }}.md\") -->{{ f }}<!-- endif --><!-- endfor -->\
+                   <!-- if lang(rust) --> rust<!-- endif -->\
+                   <!-- if contains(\"*.rs\", \"unsafe\") || dep(serde) || cargo_workspace() --> no<!-- endif -->\
+                   <!-- for l in languages --> {{ l }}<!-- endfor -->";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        for f in ["a.rs", "b.rs", "b.md"] {
+            fs::File::create(td.path().join(f)).unwrap();
+        }
+        let ctx = Context::new(td.path());
+        assert_eq!(flatten(&tpl.render_parts(&ctx).unwrap()), "ab.rs rust Rust");
+        assert_eq!(ctx.walks(), 1);
+        // Later renders with the same context reuse the index.
+        fs::File::create(td.path().join("a.md")).unwrap();
+        assert_eq!(flatten(&tpl.render_parts(&ctx).unwrap()), "ab.rs rust Rust");
+        assert_eq!(ctx.walks(), 1);
+        assert_eq!(tpl.render(td.path()).unwrap(), "aa.rsb.rs rust Rust");
+    }
+
+    #[test]
+    fn render_checks_dependencies() {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::write(
+            td.path().join("Cargo.toml"),
+            "[dependencies]\ntokio = \"1\"\n[dev-dependencies]\nproptest = \"1\"\n",
+        )
+        .unwrap();
+        let tpl = Template::parse(
+            "<!-- if dep(cargo:tokio) -->tokio<!-- elif dep(async-std) -->async-std<!-- endif -->\
+             <!-- if dep(proptest, dev) --> proptest<!-- endif -->\
+             <!-- if dep(tokio, dev) || dep(npm:tokio) --> no<!-- endif -->",
+        )
+        .unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), "tokio proptest");
+        fs::write(td.path().join("Cargo.toml"), "[dependencies\n").unwrap();
+        let err = tpl.render(td.path()).unwrap_err().to_string();
+        assert!(err.contains("Cargo.toml"), "{err}");
+    }
+
+    #[test]
+    fn render_describes_cargo_projects() {
+        let td = TempDir::new().unwrap();
+        let root = td.path();
+        let files = [
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"crates/*\"]\n[workspace.package]\nedition = \"2024\"\n",
+            ),
+            (
+                "crates/core/Cargo.toml",
+                "[package]\nname = \"core\"\nedition.workspace = true\n[features]\nstd = []\n",
+            ),
+            ("crates/core/src/lib.rs", ""),
+            (
+                "crates/cli/Cargo.toml",
+                "[package]\nname = \"cli\"\nedition = \"2021\"\n[features]\ncolor = []\n",
+            ),
+            ("crates/cli/src/main.rs", ""),
+        ];
+        fs::create_dir_all(root.join(".git")).unwrap();
+        for (path, text) in files {
+            fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+            fs::write(root.join(path), text).unwrap();
+        }
+        let tpl = Template::parse(
+            "<!-- if cargo_workspace() -->workspace {{ cargo.edition }}: {{ cargo.members }}\n<!-- endif -->\
+             bins {{ cargo.bins }}; libs {{ cargo.libs }}; features {{ cargo.features }}\n\
+             <!-- if cargo_edition(2021) && cargo_feature(std) && !cargo_feature(serde) -->ok<!-- endif -->",
+        )
+        .unwrap();
+        assert_eq!(
+            tpl.render(root).unwrap(),
+            "workspace 2024: cli, core\nbins cli; libs core; features color, std\nok"
+        );
+    }
+
+    #[test]
+    fn render_interpolates_facts() {
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::write(td.path().join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        let tpl = Template::parse("Run tests for {{ package.name }}.\nLiteral: \\{{ x }}").unwrap();
+        let out = tpl.render(td.path()).unwrap();
+        assert_eq!(out, "Run tests for demo.\nLiteral: {{ x }}");
+    }
+
+    #[test]
+    fn render_loops_over_files_and_languages() {
+        let td = TempDir::new().unwrap();
+        let root = td.path();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        for f in [
+            "crates/b/Cargo.toml",
+            "crates/a/Cargo.toml",
+            "crates/a/src/lib.rs",
+        ] {
+            let p = root.join(f);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::File::create(p).unwrap();
+        }
+        let src = "<!-- for c in files(\"crates/*/Cargo.toml\") -->\
+                   - {{ c.dir }}<!-- if exists(\"{{ c.dir }}/src/lib.rs\") --> (lib)<!-- endif -->\n\
+                   <!-- endfor -->\
+                   <!-- for l in languages -->{{ l }};<!-- endfor -->";
+        let tpl = Template::parse(src).unwrap();
+        assert_eq!(
+            tpl.render(root).unwrap(),
+            "- crates/a (lib)\n- crates/b\nRust;"
+        );
+    }
+
+    #[test]
+    fn render_applies_whitespace_control() {
+        struct Case {
+            name: &'static str,
+            input: &'static str,
+            trim_tag_lines: bool,
+            expect: &'static str,
+        }
+        let cases = vec![
+            Case {
+                name: "no markers keeps tag newlines",
+                input: "A\n<!-- if exists(\"x\") -->\nX\n<!-- endif -->\nB\n",
+                trim_tag_lines: false,
+                expect: "A\n\nB\n",
+            },
+            Case {
+                name: "right markers consume the following newline",
+                input: "A\n<!-- if exists(\"x\") --->\nX\n<!-- endif --->\nB\n",
+                trim_tag_lines: false,
+                expect: "A\nB\n",
+            },
+            Case {
+                name: "left marker consumes the preceding newline and indent",
+                input: "A\n  <!--- if exists(\"x\") -->X<!-- endif -->\nB\n",
+                trim_tag_lines: false,
+                expect: "A\nB\n",
+            },
+            Case {
+                name: "right marker also drops trailing spaces",
+                input: "<!-- if exists(\"x\") -->X<!-- else --->  \nY\n<!-- endif -->",
+                trim_tag_lines: false,
+                expect: "Y\n",
+            },
+            Case {
+                name: "standalone tag lines disappear",
+                input: "A\n  <!-- if exists(\"x\") -->\nX\n<!-- else -->\nY\n  <!-- endif -->  \nB\n",
+                trim_tag_lines: true,
+                expect: "A\nY\nB\n",
+            },
+            Case {
+                name: "tags sharing a line with text are kept",
+                input: "A <!-- if exists(\"x\") -->X<!-- endif -->\nB\n",
+                trim_tag_lines: true,
+                expect: "A \nB\n",
+            },
+            Case {
+                name: "literal comments are not control tags",
+                input: "A\n<!-- note --->\nB\n",
+                trim_tag_lines: true,
+                expect: "A\n<!-- note --->\nB\n",
+            },
+        ];
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        for c in cases {
+            let opts = ParseOptions {
+                trim_tag_lines: c.trim_tag_lines,
+                ..Default::default()
+            };
+            let tpl = Template::parse_with(c.input, opts).unwrap();
+            assert_eq!(tpl.render(td.path()).unwrap(), c.expect, "case: {}", c.name);
+        }
+    }
+
+    #[test]
+    fn render_drops_private_comments() {
+        let src = "A<!-- # owner: infra --> <!--#why-->B\n<!-- # note --->\n<!-- kept -->\n";
+        let tpl = Template::parse(src).unwrap();
+        assert!(tpl.blocks.contains(&Block::Comment {
+            text: "owner: infra".into(),
+            trim: Trim::default(),
+        }));
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), "A B\n<!-- kept -->\n");
+    }
+
+    #[test]
+    fn render_leaves_tags_in_code_alone() {
+        let src = "~~~html\n<!-- if env(A) -->\n~~~\n\
+                   Use `<!-- endif -->` in `{{ package.name }}`. \\<!-- if x -->\n\
+                   <!-- if exists(\"x\") -->hidden<!-- endif -->";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::write(td.path().join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        assert_eq!(
+            tpl.render(td.path()).unwrap(),
+            "~~~html\n<!-- if env(A) -->\n~~~\n\
+             Use `<!-- endif -->` in `{{ package.name }}`. <!-- if x -->\n"
+        );
+
+        let src = "```yaml\ntoken: ${{ secrets.TOKEN }}\n```\n{{ package.name }}\n";
+        let tpl = Template::parse(src).unwrap();
+        assert_eq!(
+            tpl.render(td.path()).unwrap(),
+            "```yaml\ntoken: ${{ secrets.TOKEN }}\n```\ndemo\n"
+        );
+    }
+
+    #[test]
+    fn render_keeps_prose_comments() {
+        let src = "<!-- for details see below -->\nhi\n\
+                   <!-- include the logs when asking -->\n\
+                   <!-- block quotes are fine -->\n\
+                   <!-- define terms before use -->\n\
+                   <!-- raw output follows -->\n\
+                   <!-- once more, with feeling -->\n\
+                   <!-- section 2 of the guide -->\n";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        assert_eq!(tpl.render(td.path()).unwrap(), src);
+    }
+
+    #[test]
+    fn render_copies_raw_blocks_exactly() {
+        let src = "a\n<!-- raw --->\n<!-- if x -->{{ y }} \\{{ ```\n<!--- endraw -->\nb";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        assert_eq!(
+            tpl.render(td.path()).unwrap(),
+            "a\n<!-- if x -->{{ y }} \\{{ ```\nb"
+        );
+    }
+
+    #[test]
+    fn render_keeps_prose_raw_comments_in_raw_blocks() {
+        let src = "<!-- raw --><!-- raw output follows -->\nx<!-- endraw -->";
+        let tpl = Template::parse(src).unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        assert_eq!(
+            tpl.render(td.path()).unwrap(),
+            "<!-- raw output follows -->\nx"
+        );
+    }
+
     #[test]
     fn render_propagates_expr_errors() {
-        let tpl = Template::parse("<!-- if exists('{oops') -->x<!-- endif -->").unwrap();
+        // Interpolated arguments can only be checked once they are bound.
+        let tpl = Template::parse(
+            "<!-- for l in languages --><!-- if lang('{{ l }}x') -->x<!-- endif --><!-- endfor -->",
+        )
+        .unwrap();
         let td = TempDir::new().unwrap();
         fs::create_dir_all(td.path().join(".git")).unwrap();
+        fs::write(td.path().join("main.rs"), "").unwrap();
         let err = tpl.render(td.path()).unwrap_err();
         match err {
-            Error::Template(_) => {}
+            Error::Located(d) => {
+                assert!(d.message.contains("unknown language"), "{}", d.message);
+                let src = d.source.as_deref().expect("source attached");
+                assert_eq!(&src.text[d.span.start..d.span.end], "lang('{{ l }}x')");
+            }
             other => panic!("unexpected error: {other:?}"),
         }
     }
+    #[test]
+    fn render_locates_missing_facts() {
+        let tpl = Template::parse("# Title\n\nName: {{ package.name }}\n").unwrap();
+        let td = TempDir::new().unwrap();
+        fs::create_dir_all(td.path().join(".git")).unwrap();
+        let err = tpl.render(td.path()).unwrap_err();
+        let Error::Located(d) = &err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(d.message, "fact package.name is not available");
+        let src = d.source.as_deref().expect("source attached");
+        assert_eq!(src.line_col(d.span.start), (3, 7));
+        assert_eq!(&src.text[d.span.start..d.span.end], "{{ package.name }}");
+        assert!(
+            err.to_string()
+                .starts_with("template render error: fact package.name is not available\n --> 3:7"),
+            "{err}"
+        );
+    }
 }
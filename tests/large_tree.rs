@@ -0,0 +1,97 @@
+//! Renders generated projects, checking that the output does not depend on
+//! the number of walker threads. The timing run over tens of thousands of
+//! files is ignored by default; run it with `cargo test -- --ignored`.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const TEMPLATE: &str = "\
+<!-- if exists(\"src/d000/f000.rs\") -->first<!-- endif -->
+<!-- if exists(\"**/missing.txt\") -->missing<!-- endif -->
+<!-- if lang(python) -->python<!-- endif -->
+<!-- if exists(\"target/**\") -->ignored<!-- endif -->
+<!-- for f in files(\"src/d01?/f00?.rs\") -->{{ f }}
+<!-- endfor -->
+<!-- for l in languages -->{{ l }}
+<!-- endfor -->";
+
+fn generate(root: &Path, dirs: usize, files_per_dir: usize) {
+    fs::create_dir_all(root.join(".git")).unwrap();
+    fs::write(root.join(".gitignore"), "target/\n").unwrap();
+    fs::write(root.join(".agents.md"), TEMPLATE).unwrap();
+    for d in 0..dirs {
+        let dir = root.join(format!("src/d{d:03}"));
+        fs::create_dir_all(&dir).unwrap();
+        for f in 0..files_per_dir {
+            let ext = if f % 10 == 9 { "py" } else { "rs" };
+            fs::write(dir.join(format!("f{f:03}.{ext}")), "").unwrap();
+        }
+    }
+    fs::create_dir_all(root.join("target")).unwrap();
+    fs::write(root.join("target/build.rs"), "").unwrap();
+}
+
+fn render(root: &Path, threads: usize) -> (String, Duration) {
+    let start = Instant::now();
+    let out = Command::new(env!("CARGO_BIN_EXE_agentsmd"))
+        .arg(root)
+        .arg("--template")
+        .arg(root.join(".agents.md"))
+        .arg("--stdout")
+        .arg("--threads")
+        .arg(threads.to_string())
+        .output()
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    (String::from_utf8(out.stdout).unwrap(), elapsed)
+}
+
+/// Render with one thread and check the expected content.
+fn render_sequential(root: &Path) -> (String, Duration) {
+    let (expected, elapsed) = render(root, 1);
+    assert!(expected.contains("first\n"), "{expected}");
+    assert!(!expected.contains("missing"), "{expected}");
+    assert!(!expected.contains("ignored"), "{expected}");
+    assert!(expected.contains("python\n"), "{expected}");
+    let listed: Vec<&str> = expected.lines().filter(|l| l.starts_with("src/")).collect();
+    // Ten directories with f000.rs to f008.rs each; f009 is Python.
+    assert_eq!(listed.len(), 10 * 9);
+    assert!(listed.is_sorted());
+    assert!(expected.ends_with("Python\nRust\n"), "{expected}");
+    (expected, elapsed)
+}
+
+#[test]
+fn output_does_not_depend_on_threads() {
+    let td = tempfile::TempDir::new().unwrap();
+    let root = td.path();
+    generate(root, 20, 20);
+
+    let (expected, _) = render_sequential(root);
+    for threads in [0, 2, 8] {
+        let (got, _) = render(root, threads);
+        assert_eq!(got, expected, "threads: {threads}");
+    }
+}
+
+#[test]
+#[ignore = "slow; reports walk times on a large tree"]
+fn large_tree_walk_times() {
+    let td = tempfile::TempDir::new().unwrap();
+    let root = td.path();
+    generate(root, 200, 100);
+
+    let (expected, sequential) = render_sequential(root);
+    let (got, parallel) = render(root, 0);
+    assert_eq!(got, expected);
+    // Timings vary too much between machines to assert on; compare them by
+    // eye with `cargo test -- --ignored --nocapture`.
+    eprintln!("1 thread: {sequential:?}, default threads: {parallel:?}");
+}